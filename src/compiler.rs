@@ -1,36 +1,132 @@
 use std::{str::Chars, iter::Peekable, fmt::Display};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use crate::vm::{Value, VM};
+use crate::vm::{OperandKind, Value, VM};
+use crate::diagnostic::{Diagnostic, Severity, Span};
+use crate::object::{Object, Relocation};
+use crate::program::Program;
+use crate::symbols::{self, SymbolTable};
 
 use super::vm;
+
+mod data;
+mod expr;
+mod format;
+mod include;
+mod linking;
+mod lint;
+mod listing;
+mod macros;
+mod scopes;
+
+use data::Region;
+pub use format::format_source;
+use listing::Placement;
+use expr::{Expr, Operator};
+
 // Scanning
 
+// The names of all directives, without the dot. Other names after a dot are local labels
+const DIRECTIVES: &[&str] = &[
+    "align", "array", "data", "endm", "entry", "equ", "export", "fill", "import", "include", "macro", "org", "pstring", "space",
+    "word", "zstring",
+];
+
+// Directives that mark the following values as data
+const DATA_DIRECTIVES: &[&str] = &["data", "pstring", "word", "zstring"];
+
 // Tokens
-enum Token<'a> {
+#[derive(Clone)]
+enum Token {
     // Labels
-    NamedLabel(&'a str), NumberLabel(usize),
+    NamedLabel(String), NumberLabel(usize),
     // Arguments to Opcodes. 
-    ImmediateNumber(vm::Value), ImmediateLabel(&'a str), String(&'a str),
+    ImmediateNumber(vm::Value), ImmediateLabel(String), String(String, StringKind),
+    // An operand computed from other values, made from the tokens below
+    Expression(Expr),
+    // Parts of expressions
+    Operator(Operator), OpenParen, CloseParen,
+    // Separators in the arguments of directives
+    Comma, OpenBracket, CloseBracket,
+    // Fields reserved by .space, .fill and .array: how many and their value
+    Fill(usize, Expr),
+    // Zeros up to the next multiple of the number, from .align
+    Align(usize),
+    // The address the following fields are placed at, from .org
+    Org(usize),
     // Opcodes
     OpCode(vm::Opcode),
+    // Directives like .data, without the dot
+    Directive(String),
     // End of String
     EndOfInput,
 }
 
-struct Compiler<'a> {
-    labels:     HashMap<&'a str, usize>,
-    had_error:  bool,
+// An index into Compiler::files
+type FileId = usize;
+
+// How a string is stored, set by the directive in front of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StringKind {
+    Plain,          // Only the characters
+    ZeroTerminated, // .zstring: the characters followed by 0
+    LengthPrefixed, // .pstring: the number of characters followed by the characters
+}
+
+// A token together with the part of the source it was scanned from
+#[derive(Clone)]
+struct SpannedToken {
+    token:      Token,
+    span:       Span,
+    file:       FileId,
+    expansion:  Option<Rc<Expansion>>, // The macro expansion that produced this token
+}
+
+// A use of a macro. Tokens from its body point to it, so that errors can show where the macro was used
+struct Expansion {
+    name:   String,
+    span:   Span, // The span of the macro name at the use site
+    file:   FileId,
+    parent: Option<Rc<Expansion>>, // The expansion the use site itself is part of
+}
+
+// A source file of the program. The first one is the file that was assembled, the others were included
+struct SourceFile {
+    path:   Option<PathBuf>, // None if the source wasn't read from a file
+    text:   String,
+}
+
+struct Compiler {
+    files:          Vec<SourceFile>,
+    options:        CompileOptions,
+    labels:         HashMap<String, usize>,
+    label_order:    Vec<String>, // The labels in the order they are defined in the source
+    macros:         HashMap<String, macros::Macro>,
+    constants:      HashMap<String, expr::Constant>,
+    expansions:     usize, // The number of macro uses expanded so far
+    entry:          Option<SpannedToken>, // The address after .entry
+    placements:     Vec<Placement>, // Where every token was placed in memory, in the order of the tokens
+    relocatable:    bool, // Assembling an object, whose addresses are relocated by the linker
+    linting:        bool, // Checking the program for likely mistakes, imported labels are allowed
+    imports:        HashMap<String, SpannedToken>, // The labels after .import, defined by other modules
+    exports:        HashMap<String, SpannedToken>, // The labels after .export
+    relocations:    Vec<Relocation>,
+    diagnostics:    Vec<Diagnostic>,
 }
 
 struct Scanner<'a> {
-    source:     &'a str,
-    iter:       Peekable<Chars<'a>>,
-    pos:        usize,
-    length:     usize,
+    source:         &'a str,
+    file:           FileId,
+    ignore_case:    bool, // Whether instructions may be written in lower case
+    iter:           Peekable<Chars<'a>>,
+    pos:            usize,
+    length:         usize,
+    diagnostics:    Vec<Diagnostic>,
 }
 
-impl<'a> Display for Token<'a> {
+impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::EndOfInput                    => write!(f,"EndOfInput"),
@@ -38,27 +134,52 @@ impl<'a> Display for Token<'a> {
             Self::NumberLabel(num)      => write!(f,"NumberLabel({})", num),
             Self::ImmediateLabel(name)   => write!(f,"ImmediateLabel({})", name),
             Self::ImmediateNumber(num)    => write!(f,"ImmediateNumber({})",num),
-            Self::String(str)            => write!(f,"String({})", str),
+            Self::String(str, kind)      => write!(f,"String({:?}, {:?})", str, kind),
+            Self::Expression(expr)       => write!(f, "Expression({})", expr),
+            Self::Operator(op)           => write!(f, "Operator({})", op),
+            Self::OpenParen                     => write!(f, "OpenParen"),
+            Self::CloseParen                    => write!(f, "CloseParen"),
+            Self::Comma                         => write!(f, "Comma"),
+            Self::OpenBracket                   => write!(f, "OpenBracket"),
+            Self::CloseBracket                  => write!(f, "CloseBracket"),
+            Self::Fill(count, value)     => write!(f, "Fill({}, {})", count, value),
+            Self::Align(n)               => write!(f, "Align({})", n),
+            Self::Org(address)           => write!(f, "Org({})", address),
             Self::OpCode(code)         => write!(f, "OpCode({})", code),
+            Self::Directive(name)        => write!(f, "Directive({})", name),
         }
     }
 }
 
 impl<'a> Scanner<'a> {
-    fn new<'b>(source: &'b str) -> Scanner<'b> {
+    fn new<'b>(source: &'b str, file: FileId, ignore_case: bool) -> Scanner<'b> {
         Scanner {
-            source:     source,
-            length:     source.chars().count(),
-            iter:       source.chars().peekable(),
-            pos:        0, // We start at the first char
+            source,
+            file,
+            ignore_case,
+            length:         source.len(), // In bytes, like pos
+            iter:           source.chars().peekable(),
+            pos:            0, // We start at the first char
+            diagnostics:    Vec::new(),
         }
     }
 
-    fn next_token(&mut self) -> Token<'a> {
-        if self.at_end() { // If we are at the end, we return EndOfInput
-            return Token::EndOfInput;
-        };
+    fn next_token(&mut self) -> SpannedToken {
+        // Whitespace, comments and illegal characters produce no token, so they are skipped
+        loop {
+            if self.at_end() { // If we are at the end, we return EndOfInput
+                return SpannedToken { token: Token::EndOfInput, span: Span::new(self.pos, self.pos), file: self.file, expansion: None };
+            };
 
+            let start = self.pos;
+            if let Some(token) = self.scan_token(start) {
+                return SpannedToken { token, span: Span::new(start, self.pos), file: self.file, expansion: None };
+            }
+        }
+    }
+
+    // Scans the token starting at start. Returns None if there was no token
+    fn scan_token(&mut self, start: usize) -> Option<Token> {
         let next_char = self.advance();
         
         match next_char {
@@ -69,138 +190,344 @@ impl<'a> Scanner<'a> {
 
                 if !self.at_end() {self.advance();} // Go over the ending newline
                 
-                return self.next_token();
+                None
             }
-            '"' => {
-                let start = self.pos;
-                while !self.at_end() && self.peek() != '"' {
+            '\'' => Some(self.character(start)),
+            '+' => Some(Token::Operator(Operator::Add)),
+            '*' => Some(Token::Operator(Operator::Multiply)),
+            '/' => Some(Token::Operator(Operator::Divide)),
+            '&' => Some(Token::Operator(Operator::And)),
+            '|' => Some(Token::Operator(Operator::Or)),
+            '(' => Some(Token::OpenParen),
+            ')' => Some(Token::CloseParen),
+            ',' => Some(Token::Comma),
+            '[' => Some(Token::OpenBracket),
+            ']' => Some(Token::CloseBracket),
+            '<' | '>' if !self.at_end() && self.peek() == next_char => {
+                self.advance();
+                Some(Token::Operator(if next_char == '<' {Operator::ShiftLeft} else {Operator::ShiftRight}))
+            }
+            // A - is the sign of a number if it stands in front of it, as in -1 or (-1), but not in end-1
+            '-' if !self.at_end() && self.peek().is_ascii_digit() && self.sign_position(start) => self.number(start, '-'),
+            '-' => Some(Token::Operator(Operator::Subtract)),
+            '.' if !self.at_end() && (self.peek().is_alphabetic() || self.peek() == '_') => {
+                while !self.at_end() && is_name_char(self.peek()) {
                     self.advance();
                 }
-
-                if!self.at_end() { // skip over "
+                let name = &self.source[start + 1..self.pos];
+                if !self.at_end() && self.peek() == ':' { // A local label like .loop:
                     self.advance();
+                    Some(Token::NamedLabel(format!(".{}", name)))
+                } else if DIRECTIVES.contains(&name) {
+                    Some(Token::Directive(name.to_string()))
+                } else {
+                    Some(Token::ImmediateLabel(format!(".{}", name)))
                 }
-                
-                let string = &self.source[start..self.pos-1];
-                return Token::String(string);
             }
+            '"' => Some(self.string(start)),
             a => {
-                if a.is_alphabetic() { 
+                if a.is_alphabetic() || a == '_' { 
                     // If we see a alphabetic character, we return a symbol
-                    return self.symbol();
-                } else if a.is_digit(10) || a == '-' { 
+                    Some(self.symbol(start))
+                } else if a.is_ascii_digit() { 
                     // If we see a digit, we return a number
-                    return self.number(a, 10);
+                    self.number(start, a)
                 } else if a.is_whitespace() { 
                     // We skip whitespace and return the next token
                     while !self.at_end() && self.peek().is_whitespace() {
                         self.advance();
                     }
 
-                    return self.next_token();
-                } else { // Otherwise, it must be an disallowed character
-                    panic!("Unexpected character: {}", next_char);
+                    None
+                } else { // Otherwise, it must be an disallowed character, which we report and skip
+                    // together with the disallowed characters directly after it
+                    while !self.at_end() && !self.peek().is_whitespace() && !starts_token(self.peek()) {
+                        self.advance();
+                    }
+                    let characters = &self.source[start..self.pos];
+                    if characters.chars().count() == 1 {
+                        self.error(start, format!("Unexpected character '{}'", a));
+                    } else {
+                        self.error(start, format!("Unexpected characters '{}'", characters));
+                    }
+                    None
                 }
             }
         
         }
     }
 
-    fn label_or_opcode(symbol: &str) -> Token {
-        match symbol {
-            "GETC"          => Token::OpCode(vm::Opcode::GETC           ),
-            "HALT"          => Token::OpCode(vm::Opcode::HALT           ),
-            "LOADI"         => Token::OpCode(vm::Opcode::LOADI          ),
-            "LOAD"          => Token::OpCode(vm::Opcode::LOAD           ),
-            "LOADIND"       => Token::OpCode(vm::Opcode::LOADIND        ),
-            "STORE"         => Token::OpCode(vm::Opcode::STORE          ),
-            "ADDI"          => Token::OpCode(vm::Opcode::ADDI           ),
-            "ADD"           => Token::OpCode(vm::Opcode::ADD            ),
-            "SUBTRACTI"     => Token::OpCode(vm::Opcode::SUBTRACTI      ),
-            "SUBTRACT"      => Token::OpCode(vm::Opcode::SUBTRACT       ),
-            "MULTIPLYI"     => Token::OpCode(vm::Opcode::MULTIPLYI      ),
-            "MULTIPLY"      => Token::OpCode(vm::Opcode::MULTIPLY       ),
-            "DIVIDEI"       => Token::OpCode(vm::Opcode::DIVIDEI        ),
-            "DIVIDE"        => Token::OpCode(vm::Opcode::DIVIDE         ),
-            "EQUALI"        => Token::OpCode(vm::Opcode::EQUALI         ),
-            "EQUAL"         => Token::OpCode(vm::Opcode::EQUAL          ),
-            "LESSI"         => Token::OpCode(vm::Opcode::LESSI          ),
-            "LESS"          => Token::OpCode(vm::Opcode::LESS           ),
-            "GREATERI"      => Token::OpCode(vm::Opcode::GREATERI       ),
-            "GREATER"       => Token::OpCode(vm::Opcode::GREATER        ),
-            "JUMP"          => Token::OpCode(vm::Opcode::JUMP           ),
-            "CJUMP"         => Token::OpCode(vm::Opcode::CJUMP          ),
-            "JUMPIFZERO"    => Token::OpCode(vm::Opcode::JUMPIFZERO     ),
-            "JUMPIFNZERO"   => Token::OpCode(vm::Opcode::JUMPIFNZERO    ),
-            "JUMPIFOVERFLOW"=> Token::OpCode(vm::Opcode::JUMPIFOVERFLOW ),
-            "MOVE"          => Token::OpCode(vm::Opcode::MOVE           ),
-            "MOVEI"         => Token::OpCode(vm::Opcode::MOVEI          ),
-            "MOVEIND"       => Token::OpCode(vm::Opcode::MOVEIND        ),
-            "PRINT"         => Token::OpCode(vm::Opcode::PRINT          ),
-            "PRINTC"        => Token::OpCode(vm::Opcode::PRINTC         ),
-            "NOOP"          => Token::OpCode(vm::Opcode::NOOP           ),
-            "JUMPLT"        => Token::OpCode(vm::Opcode::JUMPLT         ),
-            "JUMPGT"        => Token::OpCode(vm::Opcode::JUMPGT         ),
-            "STOREIND"      => Token::OpCode(vm::Opcode::STOREIND       ),
-            "REMAINDER"     => Token::OpCode(vm::Opcode::REMAINDER      ),
-            "REMAINDERI"    => Token::OpCode(vm::Opcode::REMAINDERI     ),
-            "NEGATE"        => Token::OpCode(vm::Opcode::NEGATE         ),
-            "SHIFTL"        => Token::OpCode(vm::Opcode::SHIFTL         ),
-            "SHIFTLI"       => Token::OpCode(vm::Opcode::SHIFTLI        ),
-            "SHIFTR"        => Token::OpCode(vm::Opcode::SHIFTR         ),
-            "SHIFTRI"       => Token::OpCode(vm::Opcode::SHIFTRI        ),
-            "AND"           => Token::OpCode(vm::Opcode::AND            ),
-            "ANDI"          => Token::OpCode(vm::Opcode::ANDI           ),
-            "OR"            => Token::OpCode(vm::Opcode::OR             ),
-            "ORI"           => Token::OpCode(vm::Opcode::ORI            ),
-            "XOR"           => Token::OpCode(vm::Opcode::XOR            ),
-            "XORI"          => Token::OpCode(vm::Opcode::XORI           ),
-            "NOT"           => Token::OpCode(vm::Opcode::NOT            ),
-            _               => Token::ImmediateLabel(symbol             ),
-        }
-    }
-
-    fn symbol(&mut self) -> Token<'a> {
-        let start = self.pos;
-        while !self.at_end() && self.peek().is_alphabetic() { // Skip all Label Character
+    // Tests if a - at start is in front of a value, i.e. it follows whitespace or (
+    fn sign_position(&self, start: usize) -> bool {
+        self.source[..start].chars().next_back().is_none_or(|c| c.is_whitespace() || c == '(')
+    }
+
+    fn label_or_opcode(&self, symbol: &str) -> Token {
+        match reserved(symbol, self.ignore_case) {
+            Some(opcode)    => Token::OpCode(opcode),
+            None            => Token::ImmediateLabel(symbol.to_string()),
+        }
+    }
+
+    // Scans a name of a label or an instruction. Names consist of letters, digits, _ and .
+    // and don't start with a digit
+    fn symbol(&mut self, start: usize) -> Token {
+        while !self.at_end() && is_name_char(self.peek()) { // Skip all Label Character
             self.advance();
         }
 
-        let symbol = &self.source[start-1..self.pos]; // The Characters we skipped over
+        let symbol = &self.source[start..self.pos]; // The Characters we skipped over
 
         if !self.at_end() && self.peek() == ':' {
             self.advance();
-            let result = Token::NamedLabel(symbol);
-            result
+            if let Some(opcode) = reserved(symbol, self.ignore_case) {
+                self.error(start, format!("The label can't be named like the instruction {}", opcode));
+            }
+            Token::NamedLabel(symbol.to_string())
         } else {
-            let result = Scanner::label_or_opcode(symbol);
-            result
+            self.label_or_opcode(symbol)
         }
     }
 
-    fn number(&mut self, start: char, base: u32) -> Token<'a> {
-        let sign = start != '-';
-        // The parsed number
-        let mut number: i32 = if start != '-' {start.to_digit(base).unwrap() as i32} else {0}; 
+    // Scans a number literal, first is the already consumed character at start.
+    // Numbers may have a prefix for their base (0x, 0b, 0o, 0d), a sign before or after the prefix
+    // and _ between digits
+    fn number(&mut self, start: usize, first: char) -> Option<Token> {
+        let mut negative = first == '-';
+        let first_digit = if negative { // The sign has to be followed by a digit
+            if self.at_end() || !self.peek().is_ascii_digit() {
+                self.error(start, "Expected a number after '-'");
+                return Some(Token::ImmediateNumber(0));
+            }
+            self.advance()
+        } else {
+            first
+        };
+
+        // A leading 0 may be followed by the prefix for the base
+        let mut base = 10;
+        let mut prefixed = false;
+        if first_digit == '0' && !self.at_end() {
+            if let Some(prefix_base) = Scanner::prefix_base(self.peek()) {
+                self.advance();
+                base = prefix_base;
+                prefixed = true;
+
+                if !self.at_end() && self.peek() == '-' { // The sign may follow the prefix, as in 0x-2
+                    self.advance();
+                    if negative {
+                        self.error(start, "A number can only have one sign");
+                    }
+                    negative = true;
+                }
+            }
+        }
+
+        // The parsed number. Digits past the range of a field are only counted, so that this can't overflow
+        let mut number: i64 = if prefixed {0} else {first_digit.to_digit(10).unwrap() as i64};
+        let mut digits = if prefixed {0} else {1};
+
+        // As long as we aren't at the end and the next character is a digit or a separator
+        while !self.at_end() && (self.peek().is_digit(base) || self.peek() == '_') {
+            if let Some(digit) = self.advance().to_digit(base) {
+                number = (number * base as i64 + digit as i64).min(i32::MAX as i64);
+                digits += 1;
+            }
+        }
 
-        // If we are at the end, we return a number token
-        if self.at_end() {return Token::ImmediateNumber(number as vm::Value);}
+        if digits == 0 {
+            let message = if base == 2 && !negative {
+                // 0b can't refer to a numbered label, which is why they start at 1
+                "Expected digits after the prefix for base 2. 0b is no label, numbered labels start at 1".to_string()
+            } else {
+                format!("Expected digits after the prefix for base {}", base)
+            };
+            self.error(start, message);
+            return Some(Token::ImmediateNumber(0));
+        }
+        // A numbered label is defined with 1$: and used with 1f (the next 1$) or 1b (the previous 1$)
+        if base == 10 && !prefixed && !negative {
+            if self.source[self.pos..].starts_with("$:") {
+                self.advance();
+                self.advance();
+                if number == 0 {
+                    self.error(start, "Numbered labels start at 1, since 0b is a binary number and can't refer to 0$");
+                }
+                return Some(Token::NamedLabel(format!("{}$", number)));
+            }
+            let mut rest = self.source[self.pos..].chars();
+            if let (Some(direction @ ('f' | 'b')), false) = (rest.next(), rest.next().is_some_and(char::is_alphanumeric)) {
+                self.advance();
+                return Some(Token::ImmediateLabel(format!("{}{}", number, direction)));
+            }
+        }
 
-        // As long as we aren't at the end and the next character is a digit
-        while !self.at_end() && self.peek().is_digit(base) {
-            number *= base as i32;
-            number += self.advance().to_digit(base).unwrap() as i32;
+        // Letters or digits directly after a number don't belong to its base
+        if !self.at_end() && self.peek().is_alphanumeric() {
+            while !self.at_end() && (self.peek().is_alphanumeric() || self.peek() == '_') {
+                self.advance();
+            }
+            if base == 10 && !prefixed && !self.at_end() && self.peek() == ':' { // Most likely a label like 2x:
+                self.advance();
+                self.error(start, "A label can't start with a digit");
+                return None;
+            }
+            self.error(start, format!("Invalid digit in base {} number", base));
+            return Some(Token::ImmediateNumber(0));
         }
-        if !sign { number = -number}
+
+        if negative { number = -number }
+
         // If the number is followed by a :, it is a NumberLabel
         if !self.at_end() && self.peek() == ':' {
             self.advance();
-            return Token::NumberLabel(number as usize);
-        } else {
-            return Token::ImmediateNumber(number as vm::Value);
+            if number < 0 || number > vm::Value::MAX as i64 {
+                self.error(start, format!("Number Label {} is not a valid address", number));
+                return None; // An invalid label is dropped, so it doesn't move the following fields
+            }
+            return Some(Token::NumberLabel(number as usize));
         }
+
+        // Numbers in other bases than 10 describe the bits of a field, so they may use the full 16 bits
+        let max = if base != 10 {u16::MAX as i64} else {vm::Value::MAX as i64};
+        if number < vm::Value::MIN as i64 || number > max {
+            self.error(start, format!("Number {} does not fit into a field (from {} to {})", number, vm::Value::MIN, max));
+            return Some(Token::ImmediateNumber(0));
+        }
+        Some(Token::ImmediateNumber(number as vm::Value))
     }
-    
+
+    // The base a number prefix after a leading 0 stands for
+    fn prefix_base(prefix: char) -> Option<u32> {
+        match prefix {
+            'x' => Some(16),
+            'b' => Some(2),
+            'o' => Some(8),
+            'd' => Some(10),
+            _   => None,
+        }
+    }
+
+    // Scans a string after the opening ". Strings end at the end of the line
+    fn string(&mut self, start: usize) -> Token {
+        let mut string = String::new();
+        loop {
+            if self.at_end() || self.peek() == '\n' {
+                self.error(start, "Unterminated string, expected \" before the end of the line");
+                break;
+            }
+
+            let char_start = self.pos;
+            let character = match self.advance() {
+                '"' => break,
+                '\\' => self.escape(),
+                c => Some(c),
+            };
+            match character {
+                Some(c) if c as u32 > vm::Value::MAX as u32 => {
+                    self.error(char_start, format!("Character '{}' does not fit into a field", c));
+                }
+                Some(c) => string.push(c),
+                None => {} // The escape sequence was already reported
+            }
+        }
+        Token::String(string, StringKind::Plain)
+    }
+
+    // Scans a character literal like 'A' or '\n' after the opening '
+    fn character(&mut self, start: usize) -> Token {
+        if self.at_end() || self.peek() == '\n' {
+            self.error(start, "Unterminated character literal");
+            return Token::ImmediateNumber(0);
+        }
+
+        let character = match self.advance() {
+            '\\' => self.escape(),
+            '\'' => {
+                self.error(start, "Empty character literal");
+                return Token::ImmediateNumber(0);
+            }
+            c => Some(c),
+        };
+
+        if self.at_end() || self.peek() != '\'' {
+            // Skip the rest of the literal, up to a ' on the same line
+            while !self.at_end() && self.peek() != '\n' && self.peek() != '\'' {
+                self.advance();
+            }
+            if !self.at_end() && self.peek() == '\'' {
+                self.advance();
+            }
+            self.error(start, "Expected ' after the character");
+            return Token::ImmediateNumber(0);
+        }
+        self.advance();
+
+        match character {
+            Some(c) if c as u32 <= vm::Value::MAX as u32 => Token::ImmediateNumber(c as vm::Value),
+            Some(c) => {
+                self.error(start, format!("Character '{}' does not fit into a field", c));
+                Token::ImmediateNumber(0)
+            }
+            None => Token::ImmediateNumber(0), // The escape sequence was already reported
+        }
+    }
+
+    // Scans the escape sequence after a \ and returns the character it stands for
+    fn escape(&mut self) -> Option<char> {
+        let start = self.pos - 1;
+        if self.at_end() {
+            self.error(start, "Expected an escape sequence after \\");
+            return None;
+        }
+        match self.advance() {
+            'n'     => Some('\n'),
+            't'     => Some('\t'),
+            'r'     => Some('\r'),
+            '0'     => Some('\0'),
+            '\\'    => Some('\\'),
+            '\''    => Some('\''),
+            '"'     => Some('"'),
+            'u'     => self.unicode_escape(start),
+            c       => {
+                self.error(start, format!("Unknown escape sequence '\\{}'", c));
+                None
+            }
+        }
+    }
+
+    // Scans the {1F600} after \u, with up to six hexadecimal digits
+    fn unicode_escape(&mut self, start: usize) -> Option<char> {
+        if self.at_end() || self.peek() != '{' {
+            self.error(start, "Expected a character code like \\u{E4}");
+            return None;
+        }
+        self.advance();
+
+        let mut code: u32 = 0;
+        let mut digits = 0;
+        while !self.at_end() && self.peek().is_ascii_hexdigit() {
+            code = code.saturating_mul(16) + self.advance().to_digit(16).unwrap();
+            digits += 1;
+        }
+        if self.at_end() || self.peek() != '}' || digits == 0 || digits > 6 {
+            self.error(start, "Expected a character code like \\u{E4}");
+            return None;
+        }
+        self.advance();
+
+        let character = char::from_u32(code);
+        if character.is_none() {
+            self.error(start, format!("{:X} is not a valid character code", code));
+        }
+        character
+    }
+
+    // Reports an error for the source from start to the current position
+    fn error(&mut self, start: usize, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(self.source, Span::new(start, self.pos), message));
+    }
+
     fn advance(&mut self) -> char {
         let result = self.iter.next().expect("Error: read past index");
         self.pos += result.len_utf8();
@@ -212,134 +539,573 @@ impl<'a> Scanner<'a> {
     }
 
     fn at_end(&self) -> bool {
-        return self.pos >= self.length;
+        self.pos >= self.length
     }
 
-    fn to_tokens(mut self) -> Vec<Token<'a>> {
+    // Scans the whole source, returning the tokens and all diagnostics reported while scanning
+    fn into_tokens(mut self) -> (Vec<SpannedToken>, Vec<Diagnostic>) {
         let mut result = Vec::new();
         while !self.at_end() {
             result.push(self.next_token());
         }
 
-        result
+        (result, self.diagnostics)
     }
 }
 
-pub fn compile(source: &str) -> Option<vm::VM> {
-    // The resulting vm
-    let mut vm = vm::VM::new();
+/// Settings for assembling a program
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Directories searched for `.include`d files that aren't found next to the including file
+    pub include_paths:  Vec<PathBuf>,
+    /// Also accept instructions written in lower or mixed case, like `halt`
+    pub ignore_case:    bool,
+    /// Also create a listing, which shows every source line next to the address and values
+    /// of the fields it was assembled to, followed by the addresses of all labels
+    pub listing:        bool,
+}
 
-    // The compiler state
-    let mut compiler = Compiler::new();
-    // The scanner
-    let scanner = Scanner::new(source);
-    let tokens = scanner.to_tokens();
-    
-    // Find and define all labels in the source code
-    compiler.define_labels(&tokens);
+/// Compiles the source into a vm, with the program loaded at field 0.
+///
+/// If there were errors, all diagnostics are returned instead, ordered by their position in the source.
+pub fn compile(source: &str) -> Result<vm::VM, Vec<Diagnostic>> {
+    assemble(source).map(|program| program.vm)
+}
 
-    // Parse all instruction
-    parse_ops(&mut compiler, &mut vm, tokens);
-    
-    if compiler.had_error { // If there was an error compiling the code, we return None
-        Option::None
-    } else {                // If compilation was successfull, we return the vm
-        Option::Some(vm)
+/// Like [`compile`], but also returns the labels and source locations of the program
+pub fn assemble(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    assemble_with(source, None, &CompileOptions::default())
+}
+
+/// Assembles source that was read from `file`, which is used to resolve `.include`s and
+/// is shown in diagnostics. Without a file, includes are resolved relative to the working directory.
+pub fn assemble_with(source: &str, file: Option<&Path>, options: &CompileOptions) -> Result<Program, Vec<Diagnostic>> {
+    let mut compiler = Compiler::new(options);
+    let vm = compiler.translate(source, file)?;
+
+    let listing = if options.listing {Some(compiler.listing())} else {None};
+    Ok(Program { vm, symbols: compiler.symbols(), source_map: compiler.source_map(), listing })
+}
+
+/// Assembles source into a module that is linked with others by [`link`](crate::linker::link).
+///
+/// Labels named by `.import` are defined by other modules, labels named by `.export` can be imported
+/// by other modules. Every field that holds an address is recorded as relocation
+pub fn assemble_object(source: &str, file: Option<&Path>, options: &CompileOptions) -> Result<Object, Vec<Diagnostic>> {
+    let mut compiler = Compiler::new(options);
+    compiler.relocatable = true;
+    let vm = compiler.translate(source, file)?;
+
+    let mut imports: Vec<String> = compiler.imports.keys().cloned().collect();
+    imports.sort();
+    let mut exports: Vec<String> = compiler.exports.keys().cloned().collect();
+    exports.sort();
+    Ok(Object {
+        name:           file.map(|file| file.display().to_string()).unwrap_or_else(|| "<source>".to_string()),
+        entry:          compiler.entry.as_ref().map(|_| vm.pc),
+        fields:         vm.fields,
+        symbols:        compiler.symbols(),
+        exports,
+        imports,
+        relocations:    std::mem::take(&mut compiler.relocations),
+        source_map:     compiler.source_map(),
+    })
+}
+
+/// Assembles source like [`assemble_with`] and checks it for likely mistakes, like code that is never
+/// executed or a program that doesn't end with `HALT`.
+///
+/// Returns the warnings, or all diagnostics if the source has errors. Labels named by `.import` are
+/// allowed, since they are defined by other modules
+pub fn lint(source: &str, file: Option<&Path>, options: &CompileOptions) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let mut compiler = Compiler::new(options);
+    compiler.linting = true;
+    compiler.translate(source, file)?;
+    Ok(compiler.take_diagnostics())
+}
+
+impl Compiler {
+    // Translates the source into a vm with the program loaded
+    fn translate(&mut self, source: &str, file: Option<&Path>) -> Result<VM, Vec<Diagnostic>> {
+        // The resulting vm
+        let mut vm = vm::VM::new();
+
+        let main = self.add_file(file.map(Path::to_path_buf), source.to_string());
+        // Scan the source, together with all included files
+        let tokens = self.scan_file(main, &mut Vec::new());
+
+        // Replace macros with their bodies
+        let tokens = self.expand_macros(tokens);
+
+        // Give local and numbered labels unique names
+        let tokens = self.resolve_local_labels(tokens);
+
+        // Remove the definitions of constants, they are computed once the labels are known
+        let tokens = self.collect_constants(tokens);
+
+        // Remove .import and .export
+        let tokens = self.link_directives(tokens);
+
+        // Replace .space, .fill, .align and .array with the fields they reserve
+        let mut tokens = self.data_directives(tokens);
+
+        // Apply .zstring and .pstring to their strings
+        self.string_directives(&mut tokens);
+
+        // Find and define all labels in the source code
+        self.define_labels(&tokens);
+        self.define_constants();
+        self.check_links();
+
+        // Check that all instructions have the right operands
+        self.misspelled_instructions(&mut tokens);
+        self.check_operands(&tokens);
+
+        // Parse all instruction
+        parse_ops(self, &mut vm, &tokens);
+        vm.pc = self.entry_point(vm.fields.len());
+
+        if self.diagnostics.iter().any(Diagnostic::is_error) { // If there was an error compiling the code, we return the diagnostics
+            Err(self.take_diagnostics())
+        } else {                // If compilation was successfull, we return the vm
+            if self.linting {
+                self.lint(&tokens, &vm);
+            }
+            Ok(vm)
+        }
+    }
+
+    // All diagnostics, ordered by file first, in the order the files were included
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = std::mem::take(&mut self.diagnostics);
+        diagnostics.sort_by_key(|diagnostic| (self.file_index(diagnostic), diagnostic.span.start));
+        diagnostics
+    }
+
+    // The addresses of all labels that were written in the source. Names generated for numbered labels,
+    // labels in macros and local labels without a global label before them are left out, since they
+    // can't be written in source code
+    fn symbols(&self) -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        for name in &self.label_order {
+            if symbols::is_label_name(name) {
+                symbols.insert(name.clone(), self.labels[name]);
+            }
+        }
+        symbols
     }
 }
 
-impl<'a> Compiler<'a> {
-    fn new() -> Compiler<'a> {
+impl Compiler {
+    fn new(options: &CompileOptions) -> Compiler {
         Compiler {
-            labels:     HashMap::new(),
-            had_error:  false,
+            files:          Vec::new(),
+            options:        options.clone(),
+            labels:         HashMap::new(),
+            label_order:    Vec::new(),
+            macros:         HashMap::new(),
+            constants:      HashMap::new(),
+            expansions:     0,
+            entry:          None,
+            placements:     Vec::new(),
+            relocatable:    false,
+            linting:        false,
+            imports:        HashMap::new(),
+            exports:        HashMap::new(),
+            relocations:    Vec::new(),
+            diagnostics:    Vec::new(),
+        }
+    }
+
+    fn add_file(&mut self, path: Option<PathBuf>, text: String) -> FileId {
+        self.files.push(SourceFile { path, text });
+        self.files.len() - 1
+    }
+
+    // The name of a file as shown in diagnostics
+    fn file_name(&self, file: FileId) -> Option<String> {
+        self.files[file].path.as_ref().map(|path| path.display().to_string())
+    }
+
+    // The position of the file a diagnostic is in, used to order diagnostics
+    fn file_index(&self, diagnostic: &Diagnostic) -> FileId {
+        (0..self.files.len())
+            .find(|file| self.file_name(*file) == diagnostic.file)
+            .unwrap_or(0)
+    }
+
+    // Creates a diagnostic for a span in file
+    fn diagnostic(&self, severity: Severity, file: FileId, span: Span, message: impl Into<String>) -> Diagnostic {
+        let diagnostic = Diagnostic::new(severity, &self.files[file].text, span, message);
+        match self.file_name(file) {
+            Some(name) => diagnostic.in_file(name),
+            None => diagnostic,
+        }
+    }
+
+    // Reports an error at token. If it comes from a macro, the uses of the macro are added as notes
+    fn error(&mut self, token: &SpannedToken, message: impl Into<String>) {
+        let diagnostic = self.error_at(token, message);
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Tests if an error starting at token was already reported, e.g. by the scanner
+    fn reported(&self, token: &SpannedToken) -> bool {
+        let file = self.file_name(token.file);
+        self.diagnostics.iter().any(|diagnostic| diagnostic.is_error() && diagnostic.file == file && diagnostic.span.start == token.span.start)
+    }
+
+    // The error reported by error, to add further notes
+    fn error_at(&self, token: &SpannedToken, message: impl Into<String>) -> Diagnostic {
+        self.diagnostic_at(Severity::Error, token, message)
+    }
+
+    // A diagnostic at token, with the uses of the macro it comes from as notes
+    fn diagnostic_at(&self, severity: Severity, token: &SpannedToken, message: impl Into<String>) -> Diagnostic {
+        let mut diagnostic = self.diagnostic(severity, token.file, token.span, message);
+        let mut expansion = token.expansion.as_ref();
+        while let Some(current) = expansion {
+            let note = format!("in expansion of macro '{}'", current.name);
+            diagnostic = diagnostic.with_note(self.diagnostic(Severity::Note, current.file, current.span, note));
+            expansion = current.parent.as_ref();
         }
+        diagnostic
     }
 
-    fn define_label(&mut self, name: &'a str, pos: usize) {
-        self.labels.insert(name, pos);
+    fn define_label(&mut self, name: String, pos: usize) {
+        if self.labels.insert(name.clone(), pos).is_none() {
+            self.label_order.push(name);
+        }
     }
 
     fn get_label(&self, name: &str) -> Option<usize> {
-        self.labels.get(name).map(|label| *label)
+        self.labels.get(name).copied()
     }
 
-    fn define_labels(&mut self,tokens: &Vec<Token<'a>>) {
+    fn define_labels(&mut self,tokens: &[SpannedToken]) {
         let mut pos = 0;
-        for token in tokens {
-            match token {
-                Token::NamedLabel(name) => {
-                    self.define_label(name, pos);
+        let mut definitions: HashMap<&str, &SpannedToken> = HashMap::new(); // Where every label was defined first
+        let mut regions = Vec::new(); // The fields placed by every .org, and before the first one
+        let mut region = match tokens.first() {
+            Some(first) => Region { start: 0, end: 0, token: first },
+            None => return,
+        };
+        for spanned in tokens {
+            match &spanned.token {
+                Token::Org(address) => {
+                    region.end = pos;
+                    regions.push(region);
+                    region = Region { start: *address, end: *address, token: spanned };
+                    pos = *address;
                 }
-                Token::NumberLabel(n) => {
-                    if *n >= pos {
-                        pos = *n;
-                    } else {
-                        println!("Error: Expect Number Label to be at least the position of the field it labels: got {}, expected at least {}", *n, pos);
-                        self.had_error = true;
+                Token::NamedLabel(name) => {
+                    if let Some(first) = definitions.get(name.as_str()) {
+                        let diagnostic = self.error_at(spanned, format!("The label '{}' is defined more than once", name))
+                            .with_note(self.diagnostic(Severity::Note, first.file, first.span, "first defined here"));
+                        self.diagnostics.push(diagnostic);
+                        continue;
                     }
-                },
-                Token::ImmediateNumber(_) => {
-                    pos += 1;
-                },
-                Token::ImmediateLabel(_) => {
-                    pos += 1;
-                },
-                Token::OpCode(_) => {
-                    pos += 1;
-                },
-                Token::String(str) => {
-                    pos += str.len();
+                    definitions.insert(name, spanned);
+                    self.define_label(name.clone(), pos);
                 }
-                Token::EndOfInput => {},
+                Token::NumberLabel(n) if *n < pos => {
+                    self.error(spanned, format!("Expect Number Label to be at least the position of the field it labels: got {}, expected at least {}", *n, pos));
+                },
+                token => pos += token.size(pos),
             }
         }
+        region.end = pos;
+        regions.push(region);
+        self.check_overlaps(regions);
     }
-}
 
-fn parse_ops<'a>(compiler: &mut Compiler<'a>, vm: &mut VM, tokens: Vec<Token<'a>>) {
-    let mut pos = 0; // The position in the code
-    
-    for token in tokens {
-        match token {
-            Token::NamedLabel(_) => {}, // Labels are already defined
-            Token::NumberLabel(n) => { // Numberlabel: we fill al skipped fields with 0
-                if n > pos {
-                    for _ in pos..n {
-                        vm.write_value(0);
-                        pos += 1;
+    // Sets the kind of the string after .zstring and .pstring
+    fn string_directives(&mut self, tokens: &mut [SpannedToken]) {
+        for i in 0..tokens.len() {
+            let (name, kind) = match &tokens[i].token {
+                Token::Directive(name) if name == "zstring" => (name, StringKind::ZeroTerminated),
+                Token::Directive(name) if name == "pstring" => (name, StringKind::LengthPrefixed),
+                _ => continue,
+            };
+            match tokens.get(i + 1) {
+                Some(next) if matches!(next.token, Token::String(..)) && self.same_line(&tokens[i], next) => {
+                    if let Token::String(_, string_kind) = &mut tokens[i + 1].token {
+                        *string_kind = kind;
                     }
                 }
-            },
-            Token::String(str) => {
-                for c in str.chars() {
-                    vm.write_value(c as Value);
+                _ => {
+                    let message = format!("Expected a string after .{}", name);
+                    self.error(&tokens[i], message);
                 }
             }
-            Token::ImmediateNumber(n) => { // A Number
-                vm.write_value(n);
-                pos += 1;
-            },
-            Token::ImmediateLabel(name) => { // We use a label and replace it with its position
-                let value = compiler.get_label(name);
-                match value {
-                    Some(value) => {
-                        vm.write_value(value as Value);
-                    },
-                    None => {
-                        println!("Unkown label '{}'", name);
-                        compiler.had_error = true;
+        }
+    }
+
+    // Reports unknown names at the start of a line that are written like an instruction, e.g. SUBSTRACTI,
+    // and assembles them as that instruction, so that its operands are checked
+    fn misspelled_instructions(&mut self, tokens: &mut [SpannedToken]) {
+        for i in 0..tokens.len() {
+            let name = match &tokens[i].token {
+                Token::ImmediateLabel(name) => name,
+                _ => continue,
+            };
+            let line_start = i == 0 || !self.same_line(&tokens[i - 1], &tokens[i])
+                || matches!(tokens[i - 1].token, Token::NamedLabel(_) | Token::NumberLabel(_));
+            let known = self.labels.contains_key(name) || self.constants.contains_key(name) || self.imports.contains_key(name);
+            if !line_start || known {
+                continue;
+            }
+            if let Some(opcode) = closest_mnemonic(name) {
+                let message = format!("Unknown instruction '{}', did you mean {}?", name, opcode);
+                self.error(&tokens[i], message);
+                tokens[i].token = Token::OpCode(opcode);
+            }
+        }
+    }
+
+    // Checks that every instruction is followed by the operands it expects.
+    // Values that don't belong to an instruction are data, which is allowed after labels and .data,
+    // but not directly after the operands of an instruction
+    fn check_operands(&mut self, tokens: &[SpannedToken]) {
+        let mut after_instruction = None; // The instruction whose operands were the last tokens
+        let mut i = 0;
+        while i < tokens.len() {
+            let spanned = &tokens[i];
+            i += 1;
+            match &spanned.token {
+                Token::OpCode(opcode) => {
+                    // Operands end with the line, so that a missing operand doesn't take the value on the next line
+                    let kinds = opcode.operands();
+                    after_instruction = Some(*opcode);
+                    for (found, kind) in kinds.iter().enumerate() {
+                        match tokens.get(i) {
+                            Some(operand) if operand.token.is_value() && self.operand_of(spanned, operand) => {
+                                self.check_operand(*opcode, *kind, operand);
+                                i += 1;
+                            }
+                            next => {
+                                let mut message = format!("{} expects {}, found {}", opcode, count(kinds.len(), "operand"), found);
+                                if next.is_some_and(|next| next.token.is_value()) {
+                                    message.push_str(". Operands have to be on the same line as the instruction");
+                                }
+                                self.error(spanned, message);
+                                after_instruction = None; // The values on the next line are not reported again
+                                break;
+                            }
+                        }
+                    }
+                }
+                Token::ImmediateNumber(_) | Token::ImmediateLabel(_) | Token::String(..) | Token::Expression(_) => {
+                    if let Some(opcode) = after_instruction {
+                        let message = format!("Too many operands: {} expects {}. Use .data for raw data", opcode, count(opcode.operands().len(), "operand"));
+                        self.error(spanned, message);
+                        after_instruction = None; // Only report the first superfluous operand
                     }
                 }
-                pos += 1;
+                Token::Directive(name) => {
+                    if !DATA_DIRECTIVES.contains(&name.as_str()) {
+                        self.error(spanned, format!("Unknown directive '.{}'", name));
+                    }
+                    after_instruction = None;
+                }
+                Token::NamedLabel(_) | Token::NumberLabel(_) | Token::Fill(..) | Token::Align(_) | Token::Org(_) => {
+                    after_instruction = None;
+                }
+                Token::Operator(_) | Token::OpenParen | Token::CloseParen | Token::EndOfInput => {}
+                Token::Comma | Token::OpenBracket | Token::CloseBracket => {}
+            }
+        }
+    }
+
+    // Tests if operand is on the line of instruction. Arguments of macros keep the location of the use
+    // of the macro, so they are taken to be on the line of the parameter they replace
+    fn operand_of(&self, instruction: &SpannedToken, operand: &SpannedToken) -> bool {
+        let argument = match (&instruction.expansion, &operand.expansion) {
+            (Some(expansion), Some(other)) => !Rc::ptr_eq(expansion, other),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        argument || self.same_line(instruction, operand)
+    }
+
+    fn check_operand(&mut self, opcode: vm::Opcode, kind: OperandKind, operand: &SpannedToken) {
+        match (kind, &operand.token) {
+            (_, Token::String(..)) if !self.reported(operand) => {
+                self.error(operand, format!("A string can't be an operand of {}", opcode));
+            }
+            (OperandKind::Address, Token::ImmediateNumber(n)) if *n < 0 => {
+                self.error(operand, format!("{} expects an address, which can't be negative", opcode));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Characters a token or comment can start with
+fn starts_token(c: char) -> bool {
+    c.is_alphanumeric() || "_;'\"+-*/&|(),[]<>.".contains(c)
+}
+
+// Characters that may appear in names after the first one
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+// The instruction a label, constant or macro must not be named like. With ignore_case, print is PRINT
+fn reserved(name: &str, ignore_case: bool) -> Option<vm::Opcode> {
+    if ignore_case {
+        vm::Opcode::from_mnemonic_ignore_case(name)
+    } else {
+        vm::Opcode::from_mnemonic(name)
+    }
+}
+
+// The instruction that name is most likely a misspelling of: one with at most a third of its letters
+// changed, added, removed or swapped, ignoring case
+fn closest_mnemonic(name: &str) -> Option<vm::Opcode> {
+    let name = name.to_uppercase();
+    crate::opcode::OPCODES.iter()
+        .map(|info| (edit_distance(&name, info.mnemonic), info))
+        .filter(|(distance, info)| *distance <= info.mnemonic.len() / 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, info)| info.opcode)
+}
+
+// The number of characters that have to be changed, added, removed or swapped with their neighbour
+// to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between the first i characters of a and the first j of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            distances[i][j] = if i == 0 || j == 0 {
+                i + j
+            } else {
+                let change = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+                let mut distance = change.min(distances[i - 1][j] + 1).min(distances[i][j - 1] + 1);
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    distance = distance.min(distances[i - 2][j - 2] + 1);
+                }
+                distance
+            };
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+// "no operands", "1 operand", "2 operands", ...
+fn count(count: usize, noun: &str) -> String {
+    match count {
+        0 => format!("no {}s", noun),
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
+impl Token {
+    // Tokens that are written to memory as values
+    fn is_value(&self) -> bool {
+        matches!(self, Token::ImmediateNumber(_) | Token::ImmediateLabel(_) | Token::String(..) | Token::Expression(_))
+    }
+}
+
+impl Token {
+    // The number of fields the token is written to, if it starts at pos. Both define_labels and
+    // parse_ops lay out memory with this, so that labels always point at the field they label
+    fn size(&self, pos: usize) -> usize {
+        match self {
+            Token::ImmediateNumber(_) | Token::ImmediateLabel(_) | Token::Expression(_) | Token::OpCode(_) => 1,
+            Token::String(string, kind) => string.chars().count() + kind.overhead(),
+            Token::NumberLabel(n) => n.saturating_sub(pos), // Skipped fields are filled with 0
+            Token::Fill(count, _) => *count,
+            Token::Align(n) => (n - pos % n) % n,
+            Token::NamedLabel(_) | Token::Directive(_) | Token::Org(_) => 0, // .org moves the position instead
+            Token::Operator(_) | Token::OpenParen | Token::CloseParen | Token::EndOfInput => 0,
+            Token::Comma | Token::OpenBracket | Token::CloseBracket => 0,
+        }
+    }
+}
+
+impl StringKind {
+    // The number of fields used in addition to the characters
+    fn overhead(self) -> usize {
+        match self {
+            StringKind::Plain => 0,
+            StringKind::ZeroTerminated | StringKind::LengthPrefixed => 1,
+        }
+    }
+
+    // The values a string of this kind is stored as
+    fn values(self, string: &str) -> Vec<Value> {
+        let characters = string.chars().map(|c| c as Value);
+        match self {
+            StringKind::Plain => characters.collect(),
+            StringKind::ZeroTerminated => characters.chain(std::iter::once(0)).collect(),
+            StringKind::LengthPrefixed => std::iter::once(string.chars().count() as Value).chain(characters).collect(),
+        }
+    }
+}
+
+impl SpannedToken {
+    fn is_directive(&self, name: &str) -> bool {
+        matches!(&self.token, Token::Directive(directive) if directive == name)
+    }
+}
+
+impl Compiler {
+    // Tests if both tokens are in the same file and there is no newline between them
+    fn same_line(&self, before: &SpannedToken, after: &SpannedToken) -> bool {
+        before.file == after.file
+            && before.span.end <= after.span.start
+            && !self.files[before.file].text[before.span.end..after.span.start].contains('\n')
+    }
+}
+
+fn parse_ops(compiler: &mut Compiler, vm: &mut VM, tokens: &[SpannedToken]) {
+    let mut pos = 0; // The position in the code
+    
+    for spanned in tokens {
+        let size = spanned.token.size(pos);
+        let values = match &spanned.token {
+            Token::NamedLabel(_) => Vec::new(), // Labels are already defined
+            Token::NumberLabel(_) | Token::Align(_) => vec![0; size], // We fill all skipped fields with 0
+            Token::Org(address) => { // The following fields start at the address, fields in between stay 0
+                pos = *address;
+                continue;
             },
-            Token::OpCode(c) => {   // We write the corresponding Value for the OpCode
-                vm.write_value(c as Value);
-                pos += 1;
+            Token::Fill(count, value) => {
+                let value = compiler.evaluate_at(value, spanned).unwrap_or(0);
+                vec![value; *count]
             },
+            Token::String(str, kind) => kind.values(str),
+            Token::ImmediateNumber(n) => vec![*n], // A Number
+            Token::ImmediateLabel(_) | Token::Expression(_) => { // We replace labels and constants with their values
+                let value = compiler.evaluate(spanned);
+                vec![value.unwrap_or(0)] // Errors were reported, but the following fields stay in place
+            },
+            Token::OpCode(c) => vec![*c as Value], // We write the corresponding Value for the OpCode
+            Token::Directive(_) => Vec::new(), // .data only marks the following values as data
+            Token::Operator(_) | Token::OpenParen | Token::CloseParen => Vec::new(), // Already part of expressions
+            Token::Comma | Token::OpenBracket | Token::CloseBracket => Vec::new(), // Already part of directives
             Token::EndOfInput => {
                 break;
             },
+        };
+        debug_assert_eq!(values.len(), size, "parse_ops must write Token::size fields for {}", spanned.token);
+
+        let end = pos + size;
+        if vm.fields.len() < end {
+            vm.fields.resize(end, 0);
+        }
+        vm.fields[pos..end].copy_from_slice(&values);
+        if compiler.relocatable {
+            compiler.relocate(spanned, pos);
+        }
+        if size > 0 {
+            compiler.placements.push(Placement::new(spanned, pos, values));
         }
+        pos = end;
     }
 }
\ No newline at end of file
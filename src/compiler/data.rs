@@ -0,0 +1,267 @@
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use super::{Compiler, Expr, Span, SpannedToken, Token};
+use crate::diagnostic::Severity;
+use crate::vm::Value;
+
+type Tokens = Peekable<IntoIter<SpannedToken>>;
+
+// Consecutive fields from start up to end, which are placed starting at token
+pub(super) struct Region<'a> {
+    pub(super) start:   usize,
+    pub(super) end:     usize,
+    pub(super) token:   &'a SpannedToken,
+}
+
+impl Compiler {
+    // Replaces the directives that reserve fields with the fields they reserve:
+    //
+    // .space n         n fields with 0
+    // .fill n, value   n fields with value
+    // .align n         0 up to the next multiple of n
+    // .array name[n]   the label name for n fields with 0
+    // .org address     the following fields start at address
+    //
+    // removes the commas between the values of .word a, b, c
+    // and takes the address after .entry out of the tokens
+    pub(super) fn data_directives(&mut self, tokens: Vec<SpannedToken>) -> Vec<SpannedToken> {
+        let mut output = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            let name = match &token.token {
+                Token::Directive(name) => name.clone(),
+                Token::Comma => {
+                    self.error(&token, "Unexpected ',', only the values of .word are separated by commas");
+                    continue;
+                }
+                Token::OpenBracket | Token::CloseBracket => {
+                    self.error(&token, "Unexpected bracket, only .array uses brackets");
+                    continue;
+                }
+                _ => {
+                    output.push(token);
+                    continue;
+                }
+            };
+
+            match name.as_str() {
+                "word" => {
+                    output.push(token.clone());
+                    self.words(&token, &mut tokens, &mut output);
+                }
+                "space" => {
+                    if let Some((count, argument)) = self.count(&token, &mut tokens) {
+                        output.push(joined(&token, &argument, Token::Fill(count, Expr::Number(0))));
+                    }
+                }
+                "fill" => {
+                    let count = self.count(&token, &mut tokens);
+                    let value = count.as_ref()
+                        .and_then(|(_, argument)| self.separator(argument, &mut tokens, Token::Comma, ","))
+                        .and_then(|comma| self.argument(&comma, &mut tokens, "the value to fill with"));
+                    if let (Some((count, _)), Some(value)) = (count, value) {
+                        let expr = Expr::from_token(&value.token).unwrap();
+                        output.push(joined(&token, &value, Token::Fill(count, expr)));
+                    }
+                }
+                "align" => match self.count(&token, &mut tokens) {
+                    // The padding depends on the address the linker places the module at, which isn't known yet
+                    Some(_) if self.relocatable => self.error(&token, ".align can't be used in modules, since the address of the module is only known when it is linked"),
+                    Some((0, argument)) => self.error(&argument, "Can't align to a multiple of 0"),
+                    Some((n, argument)) => output.push(joined(&token, &argument, Token::Align(n))),
+                    None => {}
+                },
+                "array" => self.array(&token, &mut tokens, &mut output),
+                "org" => {
+                    if let Some((address, argument)) = self.address(&token, &mut tokens) {
+                        output.push(joined(&token, &argument, Token::Org(address)));
+                    }
+                }
+                "entry" => self.entry(&token, &mut tokens),
+                _ => output.push(token),
+            }
+        }
+        output
+    }
+
+    // The values after .word, separated by commas
+    fn words(&mut self, directive: &SpannedToken, tokens: &mut Tokens, output: &mut Vec<SpannedToken>) {
+        let mut last = match self.argument(directive, tokens, "a value after .word") {
+            Some(value) => value,
+            None => return,
+        };
+        output.push(last.clone());
+
+        while let Some(next) = tokens.next_if(|next| self.same_line(&last, next)) {
+            let value = match next.token {
+                Token::Comma => match self.argument(&next, tokens, "a value after ','") {
+                    Some(value) => value,
+                    None => return,
+                },
+                _ if next.token.is_value() => {
+                    self.error(&next, "Expected ',' between the values of .word");
+                    next
+                }
+                _ => {
+                    self.error(&next, "Expected ',' or the end of the line after a value of .word");
+                    return;
+                }
+            };
+            output.push(value.clone());
+            last = value;
+        }
+    }
+
+    // .array name[count]
+    fn array(&mut self, directive: &SpannedToken, tokens: &mut Tokens, output: &mut Vec<SpannedToken>) {
+        let name = tokens.next_if(|name| self.same_line(directive, name));
+        let name = match name.as_ref().map(|name| &name.token) {
+            Some(Token::ImmediateLabel(label)) => SpannedToken { token: Token::NamedLabel(label.clone()), ..name.unwrap() },
+            Some(Token::OpCode(opcode)) => {
+                let message = format!("The array can't be named like the instruction {}", opcode);
+                self.error(name.as_ref().unwrap(), message);
+                return;
+            }
+            _ => {
+                self.error(directive, "Expected a name like buffer[10] after .array");
+                return;
+            }
+        };
+
+        let count = self.separator(&name, tokens, Token::OpenBracket, "[")
+            .and_then(|bracket| self.count(&bracket, tokens));
+        let (count, argument) = match count {
+            Some(count) => count,
+            None => return,
+        };
+        let close = match self.separator(&argument, tokens, Token::CloseBracket, "]") {
+            Some(close) => close,
+            None => return,
+        };
+
+        output.push(name);
+        output.push(joined(directive, &close, Token::Fill(count, Expr::Number(0))));
+    }
+
+    // The number of fields a directive reserves, together with the token it was given by
+    fn count(&mut self, after: &SpannedToken, tokens: &mut Tokens) -> Option<(usize, SpannedToken)> {
+        let (value, argument) = self.layout_value(after, tokens, "the number of fields")?;
+        if value < 0 {
+            self.error(&argument, format!("Can't reserve {} fields", value));
+            return None;
+        }
+        Some((value as usize, argument))
+    }
+
+    // The address after .org, together with the token it was given by
+    fn address(&mut self, after: &SpannedToken, tokens: &mut Tokens) -> Option<(usize, SpannedToken)> {
+        let (value, argument) = self.layout_value(after, tokens, "the address")?;
+        if value < 0 {
+            self.error(&argument, format!("Can't place fields at the negative address {}", value));
+            return None;
+        }
+        Some((value as usize, argument))
+    }
+
+    // A value that is needed to lay out memory, so it may only use numbers and constants, but no labels
+    fn layout_value(&mut self, after: &SpannedToken, tokens: &mut Tokens, what: &str) -> Option<(Value, SpannedToken)> {
+        let argument = self.argument(after, tokens, what)?;
+        let expr = Expr::from_token(&argument.token)?;
+        if let Some(label) = expr.labels().into_iter().find(|label| !self.constants.contains_key(*label)) {
+            let message = format!("Only numbers and constants can be used for {}, but '{}' is not a constant", what, label);
+            self.error(&argument, message);
+            return None;
+        }
+
+        let value = self.evaluate_at(&expr, &argument)?;
+        Some((value, argument))
+    }
+
+    // .entry address, which may be a label, so it is evaluated after all labels are defined
+    fn entry(&mut self, directive: &SpannedToken, tokens: &mut Tokens) {
+        let address = match self.argument(directive, tokens, "the address to start at after .entry") {
+            Some(address) => address,
+            None => return,
+        };
+        match &self.entry {
+            Some(first) => {
+                let note = self.diagnostic(Severity::Note, first.file, first.span, "first set here");
+                let diagnostic = self.error_at(directive, "The entry point is set more than once").with_note(note);
+                self.diagnostics.push(diagnostic);
+            }
+            None => self.entry = Some(address),
+        }
+    }
+
+    // The address execution starts at: the address after .entry, or else 0
+    pub(super) fn entry_point(&mut self, size: usize) -> usize {
+        let entry = match self.entry.clone() {
+            Some(entry) => entry,
+            None => return 0,
+        };
+        // Imported labels evaluate to 0 until they are linked, and the linker only relocates the entry point by the start of the module
+        let expr = Expr::from_token(&entry.token);
+        if let Some(label) = expr.as_ref().and_then(|expr| expr.labels().into_iter().find(|label| self.imports.contains_key(*label))) {
+            let message = format!("The entry point has to be in the module, but '{}' is imported", label);
+            self.error(&entry, message);
+            return 0;
+        }
+        match self.evaluate(&entry) {
+            Some(address) if address >= 0 && (address as usize) < size => address as usize,
+            Some(address) => {
+                let message = format!("The entry point {} is outside of the program, which has {}", address, super::count(size, "field"));
+                self.error(&entry, message);
+                0
+            }
+            None => 0, // Already reported
+        }
+    }
+
+    // Reports fields that are placed more than once, because a .org moved back into other fields
+    pub(super) fn check_overlaps(&mut self, mut regions: Vec<Region>) {
+        regions.retain(|region| region.start < region.end);
+        regions.sort_by_key(|region| region.start); // Stable, so equal starts stay in source order
+
+        let mut furthest: Option<&Region> = None; // The region that reaches furthest of the ones before
+        for region in &regions {
+            match furthest {
+                Some(before) if region.start < before.end => {
+                    let end = region.end.min(before.end) - 1;
+                    let addresses = if region.start == end {format!("address {}", end)} else {format!("addresses {} to {}", region.start, end)};
+                    let message = format!("These fields overlap with other fields at {}", addresses);
+                    let note = self.diagnostic(Severity::Note, before.token.file, before.token.span, "the other fields are placed here");
+                    let diagnostic = self.error_at(region.token, message).with_note(note);
+                    self.diagnostics.push(diagnostic);
+                    if region.end > before.end {
+                        furthest = Some(region);
+                    }
+                }
+                _ => furthest = Some(region),
+            }
+        }
+    }
+
+    // The value token after another token on the same line
+    fn argument(&mut self, after: &SpannedToken, tokens: &mut Tokens, expected: &str) -> Option<SpannedToken> {
+        let argument = tokens.next_if(|next| self.same_line(after, next) && next.token.is_value() && !matches!(next.token, Token::String(..)));
+        if argument.is_none() {
+            self.error(after, format!("Expected {}", expected));
+        }
+        argument
+    }
+
+    // The separator after another token on the same line
+    fn separator(&mut self, after: &SpannedToken, tokens: &mut Tokens, separator: Token, text: &str) -> Option<SpannedToken> {
+        let found = tokens.next_if(|next| self.same_line(after, next) && std::mem::discriminant(&next.token) == std::mem::discriminant(&separator));
+        if found.is_none() {
+            self.error(after, format!("Expected '{}'", text));
+        }
+        found
+    }
+}
+
+// The token for a directive, spanning from the directive to the last token of its arguments
+fn joined(directive: &SpannedToken, last: &SpannedToken, token: Token) -> SpannedToken {
+    SpannedToken { token, span: Span::new(directive.span.start, last.span.end), ..directive.clone() }
+}
@@ -0,0 +1,435 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+use crate::vm::Value;
+
+use super::{Compiler, Span, SpannedToken, Token};
+
+// Expressions can only be nested this deep, counting parentheses, negations and operators
+const MAX_EXPRESSION_DEPTH: usize = 64;
+
+// Binary operators in operands, like the - in `end - text`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Operator {
+    Add, Subtract, Multiply, Divide,
+    ShiftLeft, ShiftRight,
+    And, Or,
+}
+
+// An operand computed while assembling, e.g. `text+1` or `(LEN * 2) >> 1`
+#[derive(Debug, Clone)]
+pub(super) enum Expr {
+    Number(Value),
+    Label(String), // A label or a constant defined with .equ
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+// A constant defined with `.equ NAME value`
+pub(super) struct Constant {
+    name:   SpannedToken,
+    value:  SpannedToken,
+    state:  ConstantState,
+}
+
+#[derive(Clone, Copy)]
+enum ConstantState {
+    Unresolved,
+    Resolving, // Its value is being computed, seeing it again means it depends on itself
+    Resolved(Value),
+    Failed, // The error was already reported
+}
+
+// Why an expression has no value
+enum Failure {
+    Message(String),
+    Reported, // A constant used in the expression had an error, which was already reported
+}
+
+impl Operator {
+    // Operators with higher precedence bind tighter
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Or                                => 1,
+            Operator::And                               => 2,
+            Operator::ShiftLeft | Operator::ShiftRight  => 3,
+            Operator::Add | Operator::Subtract          => 4,
+            Operator::Multiply | Operator::Divide       => 5,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Add           => "+",
+            Operator::Subtract      => "-",
+            Operator::Multiply      => "*",
+            Operator::Divide        => "/",
+            Operator::ShiftLeft     => "<<",
+            Operator::ShiftRight    => ">>",
+            Operator::And           => "&",
+            Operator::Or            => "|",
+        }
+    }
+
+    fn apply(self, left: Value, right: Value) -> Result<Value, String> {
+        let overflow = || format!("The expression does not fit into a field (from {} to {})", Value::MIN, Value::MAX);
+        match self {
+            Operator::Add       => left.checked_add(right).ok_or_else(overflow),
+            Operator::Subtract  => left.checked_sub(right).ok_or_else(overflow),
+            Operator::Multiply  => left.checked_mul(right).ok_or_else(overflow),
+            Operator::Divide if right == 0 => Err("Division by zero in expression".to_string()),
+            Operator::Divide    => left.checked_div(right).ok_or_else(overflow),
+            Operator::ShiftLeft | Operator::ShiftRight if !(0..16).contains(&right) => {
+                Err(format!("Can't shift by {}, only by 0 to 15", right))
+            }
+            Operator::ShiftLeft => Ok(left << right),
+            Operator::ShiftRight => Ok(left >> right),
+            Operator::And       => Ok(left & right),
+            Operator::Or        => Ok(left | right),
+        }
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+impl Expr {
+    // The expression a single value token stands for
+    pub(super) fn from_token(token: &Token) -> Option<Expr> {
+        match token {
+            Token::ImmediateNumber(n)   => Some(Expr::Number(*n)),
+            Token::ImmediateLabel(name) => Some(Expr::Label(name.clone())),
+            Token::Expression(expr)     => Some(expr.clone()),
+            _ => None,
+        }
+    }
+
+    // Replaces the labels for which replace returns an expression
+    pub(super) fn replace_labels(&self, replace: &mut dyn FnMut(&str) -> Option<Expr>) -> Expr {
+        match self {
+            Expr::Number(n) => Expr::Number(*n),
+            Expr::Label(name) => replace(name).unwrap_or_else(|| Expr::Label(name.clone())),
+            Expr::Negate(inner) => Expr::Negate(Box::new(inner.replace_labels(replace))),
+            Expr::Binary(op, left, right) => {
+                Expr::Binary(*op, Box::new(left.replace_labels(replace)), Box::new(right.replace_labels(replace)))
+            }
+        }
+    }
+
+    // The number of nested levels of the expression, 1 for a number or label
+    fn depth(&self) -> usize {
+        match self {
+            Expr::Number(_) | Expr::Label(_) => 1,
+            Expr::Negate(inner) => inner.depth() + 1,
+            Expr::Binary(_, left, right) => left.depth().max(right.depth()) + 1,
+        }
+    }
+
+    // All labels and constants the expression uses
+    pub(super) fn labels(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Label(name) => vec![name.as_str()],
+            Expr::Negate(inner) => inner.labels(),
+            Expr::Binary(_, left, right) => [left.labels(), right.labels()].concat(),
+        }
+    }
+
+    // The token for the expression, simple expressions stay numbers or labels
+    fn into_token(self) -> Token {
+        match self {
+            Expr::Number(n) => Token::ImmediateNumber(n),
+            Expr::Label(name) => Token::ImmediateLabel(name),
+            expr => Token::Expression(expr),
+        }
+    }
+}
+
+// Writes the expression with only the parentheses that are needed
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Label(name) => write!(f, "{}", name),
+            Expr::Negate(inner) => match **inner {
+                Expr::Binary(..) => write!(f, "-({})", inner),
+                _ => write!(f, "-{}", inner),
+            },
+            Expr::Binary(op, left, right) => {
+                let needs_parens = |expr: &Expr, min: u8| matches!(expr, Expr::Binary(inner, ..) if inner.precedence() < min);
+                // The right side of an operator with the same precedence needs parentheses, as in a - (b - c)
+                if needs_parens(left, op.precedence()) {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", op)?;
+                if needs_parens(right, op.precedence() + 1) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+impl Compiler {
+    // Combines the tokens of every operand expression into one token, so that an expression
+    // takes the place of a single value in all later passes
+    pub(super) fn group_expressions(&mut self, tokens: Vec<SpannedToken>) -> Vec<SpannedToken> {
+        let mut output = Vec::new();
+        let mut pos = 0;
+        while pos < tokens.len() {
+            let token = &tokens[pos];
+            match &token.token {
+                Token::ImmediateNumber(_) | Token::ImmediateLabel(_) | Token::OpenParen | Token::Operator(Operator::Subtract) => {
+                    let start = pos;
+                    let expr = self.parse_expression(&tokens, &mut pos, 0, 0);
+                    let first = &tokens[start];
+                    let last = &tokens[pos - 1];
+                    output.push(SpannedToken {
+                        token: expr.map(Expr::into_token).unwrap_or(Token::ImmediateNumber(0)), // Keep the layout after an error
+                        span: Span::new(first.span.start, last.span.end),
+                        ..first.clone()
+                    });
+                }
+                Token::Operator(op) => {
+                    self.error(token, format!("Expected a value before '{}'", op));
+                    pos += 1;
+                }
+                Token::CloseParen => {
+                    self.error(token, "Unmatched ')'");
+                    pos += 1;
+                }
+                _ => {
+                    output.push(token.clone());
+                    pos += 1;
+                }
+            }
+        }
+        output
+    }
+
+    // Parses an expression at tokens[pos] whose operators bind at least as tight as min_precedence.
+    // Operators continue an expression only on the same line. depth is the number of levels around it
+    fn parse_expression(&mut self, tokens: &[SpannedToken], pos: &mut usize, min_precedence: u8, depth: usize) -> Option<Expr> {
+        let mut left = self.parse_operand(tokens, pos, depth)?;
+        while let Some(next) = tokens.get(*pos) {
+            let op = match next.token {
+                Token::Operator(op) if op.precedence() >= min_precedence && self.same_line(&tokens[*pos - 1], next) => op,
+                _ => break,
+            };
+            *pos += 1;
+            self.expect_value(tokens, *pos, op)?;
+            let right = self.parse_expression(tokens, pos, op.precedence() + 1, depth + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+            if depth + left.depth() > MAX_EXPRESSION_DEPTH {
+                return self.too_deep(tokens, pos);
+            }
+        }
+        Some(left)
+    }
+
+    // Reports an expression that is nested too deep and skips the rest of it, so that it is reported once
+    fn too_deep(&mut self, tokens: &[SpannedToken], pos: &mut usize) -> Option<Expr> {
+        let message = format!("The expression is nested too deep, it can have at most {} levels", MAX_EXPRESSION_DEPTH);
+        self.error(&tokens[*pos - 1], message);
+        while *pos < tokens.len() && self.same_line(&tokens[*pos - 1], &tokens[*pos]) {
+            *pos += 1;
+        }
+        None
+    }
+
+    // Parses a number, a label, a negation or an expression in parentheses
+    fn parse_operand(&mut self, tokens: &[SpannedToken], pos: &mut usize, depth: usize) -> Option<Expr> {
+        let token = &tokens[*pos];
+        *pos += 1;
+        if depth >= MAX_EXPRESSION_DEPTH {
+            return self.too_deep(tokens, pos);
+        }
+
+        match &token.token {
+            Token::ImmediateNumber(n) => Some(Expr::Number(*n)),
+            Token::ImmediateLabel(name) => Some(Expr::Label(name.clone())),
+            Token::Operator(Operator::Subtract) => {
+                self.expect_value(tokens, *pos, "-")?;
+                match self.parse_operand(tokens, pos, depth + 1)? {
+                    Expr::Number(n) if n != Value::MIN => Some(Expr::Number(-n)),
+                    inner => Some(Expr::Negate(Box::new(inner))),
+                }
+            }
+            Token::OpenParen => {
+                self.expect_value(tokens, *pos, "(")?;
+                let inner = self.parse_expression(tokens, pos, 0, depth + 1)?;
+                match tokens.get(*pos) {
+                    Some(close) if matches!(close.token, Token::CloseParen) && self.same_line(&tokens[*pos - 1], close) => {
+                        *pos += 1;
+                        Some(inner)
+                    }
+                    _ => {
+                        self.error(token, "Missing ')' for this '('");
+                        None
+                    }
+                }
+            }
+            _ => {
+                self.error(token, "Expected a number, a label or '('");
+                None
+            }
+        }
+    }
+
+    // Checks that a token follows after on the same line
+    fn expect_value(&mut self, tokens: &[SpannedToken], pos: usize, after: impl Display) -> Option<()> {
+        match tokens.get(pos) {
+            Some(next) if self.same_line(&tokens[pos - 1], next) => Some(()),
+            _ => {
+                self.error(&tokens[pos - 1], format!("Expected a value after '{}'", after));
+                None
+            }
+        }
+    }
+
+    // Removes all `.equ NAME value` definitions from tokens and remembers the constants
+    pub(super) fn collect_constants(&mut self, tokens: Vec<SpannedToken>) -> Vec<SpannedToken> {
+        let mut output = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            if !token.is_directive("equ") {
+                output.push(token);
+                continue;
+            }
+
+            let name = match tokens.next_if(|name| self.same_line(&token, name)) {
+                Some(name) => name,
+                None => {
+                    self.error(&token, "Expected the name of the constant after .equ");
+                    continue;
+                }
+            };
+            let value = tokens.next_if(|value| self.same_line(&name, value) && !matches!(value.token, Token::String(..)) && value.token.is_value());
+            // The value of a constant with an invalid name is dropped as well, so it isn't taken for data
+            let key = match &name.token {
+                Token::ImmediateLabel(key) => key.clone(),
+                Token::OpCode(opcode) => {
+                    self.error(&name, format!("The constant can't be named like the instruction {}", opcode));
+                    continue;
+                }
+                _ => {
+                    self.error(&name, "Expected the name of the constant after .equ");
+                    continue;
+                }
+            };
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    self.error(&name, "Expected the value of the constant after its name");
+                    continue;
+                }
+            };
+
+            if self.constants.contains_key(&key) {
+                self.error(&name, format!("The constant '{}' is defined more than once", key));
+                continue;
+            }
+            self.constants.insert(key, Constant { name, value, state: ConstantState::Unresolved });
+        }
+        output
+    }
+
+    // Computes the values of all constants. Labels must already be defined
+    pub(super) fn define_constants(&mut self) {
+        let mut names: Vec<String> = self.constants.keys().cloned().collect();
+        names.sort_by_key(|name| (self.constants[name].name.file, self.constants[name].name.span.start));
+
+        for name in names {
+            if self.labels.contains_key(&name) {
+                let token = self.constants[&name].name.clone();
+                self.error(&token, format!("'{}' is already defined as a label", name));
+            }
+            self.constant(&name);
+        }
+    }
+
+    fn constant(&mut self, name: &str) -> Option<Value> {
+        let constant = self.constants.get_mut(name)?;
+        match constant.state {
+            ConstantState::Resolved(value) => return Some(value),
+            ConstantState::Failed => return None,
+            ConstantState::Resolving => {
+                constant.state = ConstantState::Failed;
+                let token = constant.name.clone();
+                self.error(&token, format!("The value of the constant '{}' depends on itself", name));
+                return None;
+            }
+            ConstantState::Unresolved => constant.state = ConstantState::Resolving,
+        }
+
+        let token = constant.value.clone();
+        let value = self.evaluate(&token);
+        let constant = self.constants.get_mut(name).unwrap();
+        constant.state = match (constant.state, value) {
+            (ConstantState::Resolving, Some(value)) => ConstantState::Resolved(value),
+            _ => ConstantState::Failed,
+        };
+        value
+    }
+
+    // The expression a constant was defined with, unless computing its value failed
+    pub(super) fn constant_expr(&self, name: &str) -> Option<Expr> {
+        let constant = self.constants.get(name)?;
+        match constant.state {
+            ConstantState::Failed => None,
+            _ => Expr::from_token(&constant.value.token),
+        }
+    }
+
+    // The value of a number, label or expression token, reporting errors at the token
+    pub(super) fn evaluate(&mut self, token: &SpannedToken) -> Option<Value> {
+        let expr = Expr::from_token(&token.token)?;
+        self.evaluate_at(&expr, token)
+    }
+
+    // The value of expr, reporting errors at token
+    pub(super) fn evaluate_at(&mut self, expr: &Expr, token: &SpannedToken) -> Option<Value> {
+        match self.evaluate_expr(expr) {
+            Ok(value) => Some(value),
+            Err(Failure::Message(message)) => {
+                self.error(token, message);
+                None
+            }
+            Err(Failure::Reported) => None,
+        }
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value, Failure> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+            Expr::Label(name) => {
+                if let Some(address) = self.get_label(name) {
+                    Value::try_from(address).map_err(|_| Failure::Message(format!("The address of '{}' does not fit into a field", name)))
+                } else if self.constants.contains_key(name) {
+                    self.constant(name).ok_or(Failure::Reported)
+                } else if self.imports.contains_key(name) {
+                    Ok(0) // The linker adds the address of the imported label
+                } else {
+                    Err(Failure::Message(format!("Unknown label '{}'", name)))
+                }
+            }
+            Expr::Negate(inner) => {
+                let value = self.evaluate_expr(inner)?;
+                value.checked_neg()
+                    .ok_or_else(|| Failure::Message(format!("-({}) does not fit into a field", value)))
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.evaluate_expr(left)?;
+                let right = self.evaluate_expr(right)?;
+                op.apply(left, right).map_err(Failure::Message)
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashSet;
+
+use super::expr::Operator;
+use super::{CompileOptions, Scanner, SpannedToken, Token};
+use crate::diagnostic::{Diagnostic, Severity};
+
+// Instructions start at least in this column, labels that are longer move them further right
+const MIN_CODE_COLUMN: usize = 8;
+// Comments after code are aligned, unless the code is longer than this
+const MAX_COMMENT_COLUMN: usize = 48;
+
+// A source line, split into its parts
+#[derive(Default)]
+struct Line {
+    labels:     String,         // All labels at the start of the line, like `start:`
+    head:       String,         // The instruction, directive or macro
+    operands:   String,         // The operands or data after the head
+    comment:    Option<String>, // Including the ;
+    indented:   bool,           // A comment-only line that didn't start in the first column
+}
+
+impl Line {
+    // Has an instruction, directive, macro or data after the labels
+    fn has_code(&self) -> bool {
+        !self.head.is_empty() || !self.operands.is_empty()
+    }
+
+    fn is_blank(&self) -> bool {
+        self.labels.is_empty() && self.head.is_empty() && self.operands.is_empty() && self.comment.is_none()
+    }
+}
+
+/// Formats source code in a canonical layout, keeping its comments and lines.
+///
+/// Labels start in the first column, instructions and directives in a column after the longest label
+/// in front of them, and operands and comments are aligned. Numbers, characters and strings are
+/// written in one way, e.g. `0xff` becomes `0xFF`. Formatting the result again doesn't change it.
+/// Source that can't be scanned is returned as diagnostics instead
+pub fn format_source(source: &str, options: &CompileOptions) -> Result<String, Vec<Diagnostic>> {
+    let (tokens, diagnostics) = Scanner::new(source, 0, options.ignore_case).into_tokens();
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        return Err(diagnostics);
+    }
+
+    // Macros defined in the file, whose uses are aligned like instructions
+    let macros: HashSet<&str> = tokens.windows(2)
+        .filter(|pair| pair[0].is_directive("macro"))
+        .filter_map(|pair| match &pair[1].token {
+            Token::ImmediateLabel(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut tokens = tokens.iter().filter(|token| !matches!(token.token, Token::EndOfInput)).peekable();
+    let mut line_start = 0;
+    for text in source.split('\n') {
+        let line_end = line_start + text.len();
+        let mut line_tokens = Vec::new();
+        while let Some(token) = tokens.next_if(|token| token.span.start < line_end + 1) {
+            line_tokens.push(token);
+        }
+        let code_end = line_tokens.last().map(|token| token.span.end - line_start).unwrap_or(0);
+        lines.push(split_line(source, &line_tokens, text, code_end, &macros));
+        line_start = line_end + 1;
+    }
+
+    Ok(layout(lines))
+}
+
+// Splits the tokens of a line into labels, head and operands, and finds the comment after them
+fn split_line(source: &str, tokens: &[&SpannedToken], text: &str, code_end: usize, macros: &HashSet<&str>) -> Line {
+    let mut line = Line::default();
+    let rest = text[code_end.min(text.len())..].trim_end();
+    if let Some(start) = rest.find(';') {
+        line.comment = Some(rest[start..].to_string());
+        line.indented = tokens.is_empty() && !text.starts_with(';');
+    }
+
+    let labels = tokens.iter().take_while(|token| matches!(token.token, Token::NamedLabel(_) | Token::NumberLabel(_))).count();
+    line.labels = tokens[..labels].iter().map(|token| literal(source, token)).collect::<Vec<_>>().join(" ");
+
+    let mut rest = &tokens[labels..];
+    if let Some(first) = rest.first() {
+        let head = match &first.token {
+            Token::OpCode(_) | Token::Directive(_) => true,
+            Token::ImmediateLabel(name) => macros.contains(name.as_str()),
+            _ => false,
+        };
+        if head {
+            line.head = literal(source, first);
+            rest = &rest[1..];
+        }
+    }
+    line.operands = operands(source, rest);
+    line
+}
+
+// Joins operands with single spaces, but none inside parentheses and brackets, before commas
+// and after a - that negates the following value. A - that negates a number becomes its sign,
+// so `- 0` is written as `0`, like `-0` is
+fn operands(source: &str, tokens: &[&SpannedToken]) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&Token> = None;
+    let mut negated = false; // The previous token was a - that negates
+    let mut sign = false; // The previous token was a - that is written as the sign of this number
+    for (index, token) in tokens.iter().enumerate() {
+        let space = match (previous, &token.token) {
+            (None, _) => false,
+            _ if negated => false,
+            (Some(Token::OpenParen | Token::OpenBracket), _) => false,
+            (_, Token::CloseParen | Token::CloseBracket | Token::Comma | Token::OpenBracket) => false,
+            _ => true,
+        };
+        if space {
+            text.push(' ');
+        }
+        let negates = matches!(token.token, Token::Operator(Operator::Subtract)) && !previous.is_some_and(ends_value);
+        let folded = negates && tokens.get(index + 1).is_some_and(|next| unsigned_number(source, next).is_some());
+        match unsigned_number(source, token) {
+            Some(digits) if sign => text.push_str(&number(&format!("-{}", digits))),
+            _ if folded => {} // Written as the sign of the number after it
+            _ => text.push_str(&literal(source, token)),
+        }
+        sign = folded;
+        negated = negates;
+        previous = Some(&token.token);
+    }
+    text
+}
+
+// The text of a number that is written without a sign, and not as a character
+fn unsigned_number<'a>(source: &'a str, token: &SpannedToken) -> Option<&'a str> {
+    let text = &source[token.span.start..token.span.end];
+    match token.token {
+        Token::ImmediateNumber(_) if !text.starts_with(['-', '\'']) && !text.get(2..).is_some_and(|digits| digits.starts_with('-')) => Some(text),
+        _ => None,
+    }
+}
+
+// Tokens after which a - subtracts instead of negating
+fn ends_value(token: &Token) -> bool {
+    token.is_value() || matches!(token, Token::CloseParen | Token::CloseBracket)
+}
+
+// The canonical text of a token
+fn literal(source: &str, token: &SpannedToken) -> String {
+    let text = &source[token.span.start..token.span.end];
+    match &token.token {
+        Token::OpCode(opcode) => opcode.to_string(),
+        Token::ImmediateNumber(value) if text.starts_with('\'') => {
+            let c = std::char::from_u32(*value as u32).unwrap_or('\0');
+            format!("'{}'", escape(c, '\''))
+        }
+        Token::ImmediateNumber(_) => number(text),
+        Token::NumberLabel(_) => format!("{}:", number(text.trim_end_matches(':'))),
+        Token::String(string, _) => format!("\"{}\"", string.chars().map(|c| escape(c, '"')).collect::<String>()),
+        _ => text.to_string(),
+    }
+}
+
+// A number with its sign first, a lower case prefix and upper case hex digits. Decimal numbers lose
+// their 0d prefix and leading zeros
+fn number(text: &str) -> String {
+    let (mut negative, rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (prefix, digits) = match rest.get(..2) {
+        Some(prefix @ ("0x" | "0b" | "0o" | "0d")) => (prefix, &rest[2..]),
+        _ => ("", rest),
+    };
+    let digits = match digits.strip_prefix('-') {
+        Some(digits) => {
+            negative = true;
+            digits
+        }
+        None => digits,
+    };
+
+    let digits = match prefix {
+        "" | "0d" => {
+            let trimmed = digits.trim_start_matches(['0', '_']);
+            if trimmed.is_empty() {
+                negative = false; // -0 is 0
+                "0".to_string()
+            } else {
+                trimmed.to_string()
+            }
+        }
+        _ => digits.to_uppercase(),
+    };
+    let prefix = if prefix == "0d" {""} else {prefix};
+    format!("{}{}{}", if negative {"-"} else {""}, prefix, digits)
+}
+
+// A character as it is written between quote characters
+fn escape(c: char, quote: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\r' => "\\r".to_string(),
+        '\0' => "\\0".to_string(),
+        '\\' => "\\\\".to_string(),
+        c if c == quote => format!("\\{}", c),
+        c if c.is_control() => format!("\\u{{{:X}}}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+// Puts the parts of all lines into their columns
+fn layout(lines: Vec<Line>) -> String {
+    let code_column = lines.iter()
+        .filter(|line| !line.labels.is_empty() && line.has_code())
+        .map(|line| line.labels.chars().count() + 1)
+        .fold(MIN_CODE_COLUMN, usize::max);
+    let head_width = lines.iter()
+        .filter(|line| !line.operands.is_empty())
+        .map(|line| line.head.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let codes: Vec<String> = lines.iter().map(|line| code(line, code_column, head_width)).collect();
+    let comment_column = lines.iter().zip(&codes)
+        .filter(|(line, code)| line.comment.is_some() && !code.is_empty())
+        .map(|(_, code)| code.chars().count() + 2)
+        .filter(|column| *column <= MAX_COMMENT_COLUMN)
+        .max()
+        .unwrap_or(0);
+
+    let mut output = String::new();
+    let mut blank = true; // Blank lines at the start are removed, several blank lines become one
+    for (line, code) in lines.iter().zip(codes) {
+        if line.is_blank() {
+            if !blank {
+                output.push('\n');
+            }
+            blank = true;
+            continue;
+        }
+        blank = false;
+
+        let mut text = code;
+        if let Some(comment) = &line.comment {
+            let width = text.chars().count();
+            if text.is_empty() {
+                text = if line.indented {" ".repeat(code_column)} else {String::new()};
+            } else if width + 2 <= comment_column {
+                text.push_str(&" ".repeat(comment_column - width));
+            } else {
+                text.push_str("  ");
+            }
+            text.push_str(comment);
+        }
+        output.push_str(&text);
+        output.push('\n');
+    }
+    // Blank lines at the end are removed
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+    output
+}
+
+// The labels, head and operands of a line in their columns
+fn code(line: &Line, code_column: usize, head_width: usize) -> String {
+    let mut text = line.labels.clone();
+    if !line.has_code() {
+        return text;
+    }
+    text.push_str(&" ".repeat(code_column - text.chars().count()));
+    if line.head.is_empty() {
+        text.push_str(&line.operands);
+    } else if line.operands.is_empty() {
+        text.push_str(&line.head);
+    } else {
+        text.push_str(&format!("{:width$} {}", line.head, line.operands, width = head_width));
+    }
+    text
+}
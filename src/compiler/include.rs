@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{Compiler, FileId, Scanner, SpannedToken, Token};
+
+// A file that is being scanned, while the files it includes are scanned
+pub(super) struct Including {
+    file:       FileId,
+    canonical:  PathBuf, // Used to recognize the file however it is named
+}
+
+impl Compiler {
+    // Scans a file and replaces every `.include "path"` with the tokens of the included file.
+    // including holds the files whose includes are currently being scanned, to detect cycles
+    pub(super) fn scan_file(&mut self, file: FileId, including: &mut Vec<Including>) -> Vec<SpannedToken> {
+        let (tokens, diagnostics) = Scanner::new(&self.files[file].text, file, self.options.ignore_case).into_tokens();
+        let name = self.file_name(file);
+        self.diagnostics.extend(diagnostics.into_iter().map(|diagnostic| match &name {
+            Some(name) => diagnostic.in_file(name.clone()),
+            None => diagnostic,
+        }));
+        let tokens = self.group_expressions(tokens);
+
+        let canonical = self.files[file].path.as_ref().and_then(|path| fs::canonicalize(path).ok());
+        if let Some(canonical) = &canonical {
+            including.push(Including { file, canonical: canonical.clone() });
+        }
+
+        let mut output = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            if !token.is_directive("include") {
+                output.push(token);
+                continue;
+            }
+
+            let path = tokens.next_if(|path| matches!(path.token, Token::String(..)) && self.same_line(&token, path));
+            match path {
+                Some(path) => output.extend(self.include(&path, including)),
+                None => self.error(&token, "Expected the path of the file in quotes after .include"),
+            }
+        }
+
+        if canonical.is_some() {
+            including.pop();
+        }
+        output
+    }
+
+    // Scans the file named by the string token path
+    fn include(&mut self, path: &SpannedToken, including: &mut Vec<Including>) -> Vec<SpannedToken> {
+        let name = match &path.token {
+            Token::String(name, _) => name.clone(),
+            _ => return Vec::new(),
+        };
+
+        let resolved = match self.resolve(path.file, &name) {
+            Some(resolved) => resolved,
+            None => {
+                self.error(path, format!("Could not find the included file '{}'", name));
+                return Vec::new();
+            }
+        };
+
+        let canonical = fs::canonicalize(&resolved).unwrap_or_else(|_| resolved.clone());
+        if let Some(cycle_start) = including.iter().position(|active| active.canonical == canonical) {
+            let mut cycle: Vec<String> = including[cycle_start..].iter()
+                .filter_map(|active| self.file_name(active.file))
+                .collect();
+            cycle.push(resolved.display().to_string());
+            self.error(path, format!("Include cycle: {}", cycle.join(" -> ")));
+            return Vec::new();
+        }
+
+        let text = match fs::read_to_string(&resolved) {
+            Ok(text) => text,
+            Err(error) => {
+                self.error(path, format!("Could not read the included file '{}': {}", resolved.display(), error));
+                return Vec::new();
+            }
+        };
+
+        let file = self.add_file(Some(resolved), text);
+        let mut tokens = self.scan_file(file, including);
+        // The end of an included file is not the end of the program
+        tokens.retain(|token| !matches!(token.token, Token::EndOfInput));
+        tokens
+    }
+
+    // Finds an included file next to the including file or in one of the include paths
+    fn resolve(&self, including: FileId, name: &str) -> Option<PathBuf> {
+        // Source that wasn't read from a file includes relative to the working directory
+        let directory = self.files[including].path.as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        std::iter::once(directory)
+            .chain(self.options.include_paths.iter().cloned())
+            .map(|directory| directory.join(name))
+            .find(|candidate| candidate.is_file())
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use super::expr::Operator;
+use super::{Compiler, Expr, SpannedToken, Token};
+use crate::object::Relocation;
+
+type Tokens = Peekable<IntoIter<SpannedToken>>;
+
+// How a value depends on where the linker places the modules:
+// how often the start of the module and the address of each import are added to it
+#[derive(Default)]
+struct Dependence {
+    base:       i32,
+    imports:    BTreeMap<String, i32>,
+}
+
+impl Dependence {
+    fn scaled(mut self, factor: i32) -> Dependence {
+        self.base *= factor;
+        for count in self.imports.values_mut() {
+            *count *= factor;
+        }
+        self
+    }
+
+    fn added(mut self, other: Dependence) -> Dependence {
+        self.base += other.base;
+        for (name, count) in other.imports {
+            *self.imports.entry(name).or_default() += count;
+        }
+        self.imports.retain(|_, count| *count != 0);
+        self
+    }
+
+    fn is_absolute(&self) -> bool {
+        self.base == 0 && self.imports.is_empty()
+    }
+}
+
+impl Compiler {
+    // Takes `.import name, ...` and `.export name, ...` out of the tokens and remembers the names
+    pub(super) fn link_directives(&mut self, tokens: Vec<SpannedToken>) -> Vec<SpannedToken> {
+        let mut output = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            let import = match &token.token {
+                Token::Directive(name) if name == "import" => true,
+                Token::Directive(name) if name == "export" => false,
+                _ => {
+                    output.push(token);
+                    continue;
+                }
+            };
+
+            let directive = if import {".import"} else {".export"};
+            for (name, token) in self.names(&token, directive, &mut tokens) {
+                let names = if import {&mut self.imports} else {&mut self.exports};
+                names.entry(name).or_insert(token);
+            }
+        }
+        output
+    }
+
+    // The labels after .import or .export, separated by commas
+    fn names(&mut self, directive: &SpannedToken, text: &str, tokens: &mut Tokens) -> Vec<(String, SpannedToken)> {
+        let mut names = Vec::new();
+        let mut last = directive.clone();
+        loop {
+            let name = tokens.next_if(|name| self.same_line(&last, name) && matches!(name.token, Token::ImmediateLabel(_)));
+            match name {
+                Some(name) => {
+                    if let Token::ImmediateLabel(label) = &name.token {
+                        names.push((label.clone(), name.clone()));
+                    }
+                    last = name;
+                }
+                None => {
+                    self.error(&last, format!("Expected the name of a label after {}", if names.is_empty() {text} else {"','"}));
+                    return names;
+                }
+            }
+            match tokens.next_if(|comma| self.same_line(&last, comma) && matches!(comma.token, Token::Comma)) {
+                Some(comma) => last = comma,
+                None => return names,
+            }
+        }
+    }
+
+    // Checks the imported and exported names once all labels and constants are defined
+    pub(super) fn check_links(&mut self) {
+        let mut imports: Vec<(String, SpannedToken)> = self.imports.iter().map(|(name, token)| (name.clone(), token.clone())).collect();
+        imports.sort_by_key(|(_, token)| (token.file, token.span.start));
+        for (name, token) in imports {
+            if !self.relocatable && !self.linting {
+                self.error(&token, format!("'{}' is imported, so the file has to be assembled as an object and linked", name));
+            } else if self.labels.contains_key(&name) {
+                self.error(&token, format!("'{}' is imported, but also defined as a label", name));
+            } else if self.constants.contains_key(&name) {
+                self.error(&token, format!("'{}' is imported, but also defined as a constant", name));
+            }
+        }
+
+        let mut exports: Vec<(String, SpannedToken)> = self.exports.iter().map(|(name, token)| (name.clone(), token.clone())).collect();
+        exports.sort_by_key(|(_, token)| (token.file, token.span.start));
+        for (name, token) in exports {
+            if self.constants.contains_key(&name) {
+                self.error(&token, format!("Only labels can be exported, but '{}' is a constant", name));
+            } else if !self.labels.contains_key(&name) {
+                self.error(&token, format!("The exported label '{}' is not defined", name));
+            }
+        }
+    }
+
+    // Records that the fields a value token placed at address have to be relocated,
+    // if the value depends on where the module is placed
+    pub(super) fn relocate(&mut self, token: &SpannedToken, address: usize) {
+        let (expr, count) = match &token.token {
+            Token::Fill(count, expr) => (expr.clone(), *count),
+            other => match Expr::from_token(other) {
+                Some(expr) => (expr, 1),
+                None => return,
+            },
+        };
+
+        let dependence = match self.dependence(&expr) {
+            Ok(dependence) => dependence,
+            Err(message) => {
+                self.error(token, message);
+                return;
+            }
+        };
+        let imports: Vec<(&String, &i32)> = dependence.imports.iter().collect();
+        let symbol = match (dependence.base, imports.as_slice()) {
+            (0, []) => return,
+            (1, []) => None,
+            (0, [(name, 1)]) => Some((*name).clone()),
+            _ => {
+                let message = format!("The linker can't relocate '{}', it has to be the address of one label plus or minus a number", expr);
+                self.error(token, message);
+                return;
+            }
+        };
+        for address in address..address + count {
+            self.relocations.push(Relocation { address, symbol: symbol.clone() });
+        }
+    }
+
+    fn dependence(&self, expr: &Expr) -> Result<Dependence, String> {
+        match expr {
+            Expr::Number(_) => Ok(Dependence::default()),
+            Expr::Label(name) if self.labels.contains_key(name) => Ok(Dependence { base: 1, ..Dependence::default() }),
+            Expr::Label(name) if self.imports.contains_key(name) => {
+                Ok(Dependence { base: 0, imports: std::iter::once((name.clone(), 1)).collect() })
+            }
+            Expr::Label(name) => match self.constant_expr(name) {
+                Some(value) => self.dependence(&value),
+                None => Ok(Dependence::default()), // Unknown labels and failed constants were already reported
+            },
+            Expr::Negate(inner) => Ok(self.dependence(inner)?.scaled(-1)),
+            Expr::Binary(Operator::Add, left, right) => Ok(self.dependence(left)?.added(self.dependence(right)?)),
+            Expr::Binary(Operator::Subtract, left, right) => Ok(self.dependence(left)?.added(self.dependence(right)?.scaled(-1))),
+            Expr::Binary(_, left, right) => {
+                if self.dependence(left)?.is_absolute() && self.dependence(right)?.is_absolute() {
+                    Ok(Dependence::default())
+                } else {
+                    Err(format!("The linker can't relocate '{}', addresses can only be added and subtracted", expr))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use super::{Compiler, Expr, Severity, SpannedToken, Token};
+use crate::vm::{Opcode, Value, VM};
+
+// What a field of the program was assembled from, given by the index of the token
+#[derive(Clone, Copy)]
+enum Field {
+    Padding,            // Zeros from .org, .align or a numbered label, which execute as NOOP
+    Instruction(usize), // The opcode of an instruction
+    Operand(usize),
+    Data(usize),
+}
+
+// How execution gets to an address
+#[derive(Clone, Copy)]
+enum Flow {
+    Start,          // The entry point, an exported label or an address loaded for CJUMP
+    Next(usize),    // After the instruction at the address
+    Jump(usize),    // By the jump at the address
+}
+
+impl Compiler {
+    // Warns about likely mistakes in the assembled program, which has no errors
+    pub(super) fn lint(&mut self, tokens: &[SpannedToken], vm: &VM) {
+        let fields = layout(tokens, vm.fields.len());
+        self.unused_labels(tokens);
+        self.unreachable_code(tokens, vm, &fields);
+        self.stores_into_code(tokens, vm, &fields);
+        self.loaded_addresses(tokens, &fields);
+    }
+
+    fn warning(&mut self, token: &SpannedToken, message: impl Into<String>) {
+        let diagnostic = self.diagnostic_at(Severity::Warning, token, message);
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Labels that no operand, data, constant, .entry or .export refers to
+    fn unused_labels(&mut self, tokens: &[SpannedToken]) {
+        let mut used: HashSet<String> = self.exports.keys().cloned().collect();
+        let mut exprs: Vec<Expr> = tokens.iter().filter_map(|token| match &token.token {
+            Token::Fill(_, expr) => Some(expr.clone()),
+            other => Expr::from_token(other),
+        }).collect();
+        exprs.extend(self.constants.keys().filter_map(|name| self.constant_expr(name)));
+        exprs.extend(self.entry.as_ref().and_then(|entry| Expr::from_token(&entry.token)));
+        for expr in &exprs {
+            used.extend(expr.labels().into_iter().map(str::to_string));
+        }
+
+        for token in tokens {
+            match &token.token {
+                // Labels in macros are used by the macro itself, or belong to its interface
+                Token::NamedLabel(name) if !used.contains(name) && token.expansion.is_none() => {
+                    let text = self.files[token.file].text[token.span.start..token.span.end].trim_end_matches(':').to_string();
+                    self.warning(token, format!("The label '{}' is never used", text));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Follows all paths execution can take from the entry point, to find code that is never executed,
+    // data that is executed and the ends of the program that don't HALT
+    fn unreachable_code(&mut self, tokens: &[SpannedToken], vm: &VM, fields: &[Field]) {
+        let mut starts = vec![vm.pc];
+        for name in self.exports.keys() {
+            starts.extend(self.get_label(name));
+        }
+        // With CJUMP, every address of code that the program uses as value may be jumped to
+        if fields.iter().any(|field| matches!(field, Field::Instruction(i) if matches!(tokens[*i].token, Token::OpCode(Opcode::CJUMP)))) {
+            for (address, field) in fields.iter().enumerate() {
+                if let Field::Operand(i) | Field::Data(i) = field {
+                    let label = Expr::from_token(&tokens[*i].token).is_some_and(|expr| !expr.labels().is_empty());
+                    let value = usize::try_from(vm.fields[address]).ok();
+                    if label && value.is_some_and(|value| matches!(fields.get(value), Some(Field::Instruction(_)))) {
+                        starts.extend(value);
+                    }
+                }
+            }
+        }
+
+        let mut reached = vec![false; fields.len()];
+        let mut executed_data = HashSet::new(); // The data tokens already reported
+        let mut pending: Vec<(usize, Flow)> = starts.into_iter().map(|address| (address, Flow::Start)).collect();
+        while let Some((mut address, flow)) = pending.pop() {
+            while let Some(Field::Padding) = fields.get(address) {
+                address += 1;
+            }
+            let i = match fields.get(address) {
+                Some(Field::Instruction(i)) => *i,
+                Some(Field::Operand(i) | Field::Data(i)) => {
+                    if executed_data.insert(*i) {
+                        self.executed_data(&tokens[*i], flow, fields, tokens);
+                    }
+                    continue;
+                }
+                Some(Field::Padding) => unreachable!(),
+                None => {
+                    if let Flow::Next(from) = flow {
+                        let token = &tokens[instruction(fields, from).unwrap()];
+                        self.warning(token, "Execution continues past the end of the program after this instruction. End the program with HALT");
+                    }
+                    continue;
+                }
+            };
+            if reached[address] {
+                continue;
+            }
+            reached[address] = true;
+
+            let opcode = match tokens[i].token {
+                Token::OpCode(opcode) => opcode,
+                _ => continue,
+            };
+            let next = address + 1 + opcode.operands().len();
+            match opcode {
+                Opcode::HALT | Opcode::CJUMP => {}
+                Opcode::JUMP => pending.extend(self.target(&tokens[i + 1], vm.fields[address + 1]).map(|target| (target, Flow::Jump(address)))),
+                Opcode::JUMPIFZERO | Opcode::JUMPIFNZERO | Opcode::JUMPLT | Opcode::JUMPGT | Opcode::JUMPIFOVERFLOW => {
+                    pending.extend(self.target(&tokens[i + 1], vm.fields[address + 1]).map(|target| (target, Flow::Jump(address))));
+                    pending.push((next, Flow::Next(address)));
+                }
+                _ => pending.push((next, Flow::Next(address))),
+            }
+        }
+
+        // Report the first instruction of every run of instructions that are never executed
+        let mut previous: Option<(usize, usize)> = None; // The end and address of the last instruction
+        for (address, field) in fields.iter().enumerate() {
+            let i = match field {
+                Field::Instruction(i) => *i,
+                Field::Operand(_) | Field::Padding => continue,
+                Field::Data(_) => {
+                    previous = None;
+                    continue;
+                }
+            };
+            let before = previous.filter(|(end, _)| *end == address).map(|(_, before)| before);
+            let run_start = before.is_none_or(|before| reached[before]);
+            if !reached[address] && run_start {
+                let mut diagnostic = self.diagnostic_at(Severity::Warning, &tokens[i], "This code is never executed");
+                if let Some(before) = before.and_then(|before| instruction(fields, before)) {
+                    if let Token::OpCode(opcode @ (Opcode::JUMP | Opcode::HALT | Opcode::CJUMP)) = tokens[before].token {
+                        let note = format!("execution doesn't continue after this {}", opcode);
+                        diagnostic = diagnostic.with_note(self.diagnostic_at(Severity::Note, &tokens[before], note));
+                    }
+                }
+                self.diagnostics.push(diagnostic);
+            }
+            let length = match tokens[i].token {
+                Token::OpCode(opcode) => 1 + opcode.operands().len(),
+                _ => 1,
+            };
+            previous = Some((address + length, address));
+        }
+    }
+
+    // Reports data that execution reaches, which is run as if it was code
+    fn executed_data(&mut self, token: &SpannedToken, flow: Flow, fields: &[Field], tokens: &[SpannedToken]) {
+        let mut diagnostic = self.diagnostic_at(Severity::Warning, token, "This data is executed as code");
+        let note = match flow {
+            Flow::Start => None,
+            Flow::Next(from) => Some((from, "execution continues into the data after this instruction")),
+            Flow::Jump(from) => Some((from, "this instruction jumps into the data")),
+        };
+        if let Some((from, note)) = note {
+            let from = &tokens[instruction(fields, from).unwrap()];
+            diagnostic = diagnostic.with_note(self.diagnostic_at(Severity::Note, from, note));
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    // The address an operand refers to. Imported labels are outside of the program
+    fn target(&self, operand: &SpannedToken, value: Value) -> Option<usize> {
+        let imported = Expr::from_token(&operand.token)
+            .is_some_and(|expr| expr.labels().iter().any(|name| self.imports.contains_key(*name)));
+        if imported {
+            None
+        } else {
+            usize::try_from(value).ok()
+        }
+    }
+
+    // Instructions that write into the fields of instructions, so the program modifies itself
+    fn stores_into_code(&mut self, tokens: &[SpannedToken], vm: &VM, fields: &[Field]) {
+        for (address, field) in fields.iter().enumerate() {
+            // The instruction and which of its operands is the field written to
+            let (i, opcode, written) = match field {
+                Field::Instruction(i) => match tokens[*i].token {
+                    Token::OpCode(opcode @ Opcode::STORE) => (*i, opcode, 1),
+                    Token::OpCode(opcode @ (Opcode::MOVE | Opcode::MOVEI)) => (*i, opcode, 2),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let operand = &tokens[i + written];
+            let overwritten = self.target(operand, vm.fields[address + written]).and_then(|target| instruction(fields, target));
+            if let Some(overwritten) = overwritten {
+                let message = format!("{} writes into the code of the program, which modifies itself", opcode);
+                let diagnostic = self.diagnostic_at(Severity::Warning, operand, message)
+                    .with_note(self.diagnostic_at(Severity::Note, &tokens[overwritten], "this instruction is overwritten"));
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    // LOADI with the label of data loads its address, where LOAD was likely meant to load its value.
+    // Addresses loaded for LOADIND, MOVEIND, CJUMP or STORE are pointers, which are fine
+    fn loaded_addresses(&mut self, tokens: &[SpannedToken], fields: &[Field]) {
+        for (address, field) in fields.iter().enumerate() {
+            let i = match field {
+                Field::Instruction(i) if matches!(tokens[*i].token, Token::OpCode(Opcode::LOADI)) => *i,
+                _ => continue,
+            };
+            let name = match &tokens[i + 1].token {
+                Token::ImmediateLabel(name) => name,
+                _ => continue,
+            };
+            let data = self.get_label(name).is_some_and(|label| matches!(fields.get(label), Some(Field::Data(_))));
+            let pointer = match fields.get(address + 2) {
+                Some(Field::Instruction(next)) => matches!(tokens[*next].token,
+                    Token::OpCode(Opcode::LOADIND | Opcode::MOVEIND | Opcode::CJUMP | Opcode::STORE)),
+                _ => false,
+            };
+            if data && !pointer {
+                let message = format!("LOADI loads the address of '{}'. Use LOAD to load its value", name);
+                self.warning(&tokens[i + 1], message);
+            }
+        }
+    }
+}
+
+// What every field of the program was assembled from
+fn layout(tokens: &[SpannedToken], size: usize) -> Vec<Field> {
+    let mut fields = vec![Field::Padding; size];
+    let mut pos = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        match &token.token {
+            Token::Org(address) => pos = *address,
+            Token::OpCode(opcode) => {
+                fields[pos] = Field::Instruction(i);
+                pos += 1;
+                for _ in opcode.operands() { // All operands are there, since there were no errors
+                    i += 1;
+                    fields[pos] = Field::Operand(i);
+                    pos += 1;
+                }
+            }
+            Token::Fill(..) | Token::ImmediateNumber(_) | Token::ImmediateLabel(_) | Token::String(..) | Token::Expression(_) => {
+                let end = pos + token.token.size(pos);
+                fields[pos..end].fill(Field::Data(i));
+                pos = end;
+            }
+            other => pos += other.size(pos),
+        }
+        i += 1;
+    }
+    fields
+}
+
+// The opcode token of the instruction that includes the field at address, if it is part of one
+fn instruction(fields: &[Field], mut address: usize) -> Option<usize> {
+    loop {
+        match fields.get(address)? {
+            Field::Instruction(i) => return Some(*i),
+            Field::Operand(_) if address > 0 => address -= 1,
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use super::{Compiler, FileId, Span, SpannedToken};
+use crate::source_map::{SourceLocation, SourceMap};
+use crate::vm::Value;
+
+// The number of field values shown in one row of the listing
+const VALUES_PER_ROW: usize = 4;
+
+// The fields a token was placed in
+pub(super) struct Placement {
+    pub(super) file:    FileId, // Where the token was written. Tokens from macros belong to the use of the macro
+    pub(super) span:    Span,
+    pub(super) address: usize,
+    pub(super) values:  Vec<Value>,
+}
+
+impl Placement {
+    pub(super) fn new(token: &SpannedToken, address: usize, values: Vec<Value>) -> Placement {
+        let (file, span) = match &token.expansion {
+            Some(expansion) => {
+                let mut outermost = expansion;
+                while let Some(parent) = &outermost.parent {
+                    outermost = parent;
+                }
+                (outermost.file, outermost.span)
+            }
+            None => (token.file, token.span),
+        };
+        Placement { file, span, address, values }
+    }
+}
+
+impl Compiler {
+    // Every source line with the address and values of the fields it was assembled to,
+    // followed by the addresses of all labels
+    pub(super) fn listing(&self) -> String {
+        let mut output = String::new();
+        for file in 0..self.files.len() {
+            if self.files.len() > 1 {
+                let name = self.file_name(file).unwrap_or_else(|| "<source>".to_string());
+                output.push_str(&format!("; {}\n", name));
+            }
+            self.list_file(file, &mut output);
+        }
+
+        output.push_str("\nSymbols:\n");
+        for (name, address) in self.symbols().iter() {
+            output.push_str(&format!("{:>5}  {}\n", address, name));
+        }
+        output
+    }
+
+    fn list_file(&self, file: FileId, output: &mut String) {
+        let text = &self.files[file].text;
+        let lines: Vec<&str> = text.lines().collect();
+        let width = lines.len().to_string().len();
+
+        // The runs of consecutive fields on every line: their address and values
+        let mut fields: Vec<Vec<(usize, Vec<Value>)>> = vec![Vec::new(); lines.len()];
+        for placement in self.placements.iter().filter(|placement| placement.file == file) {
+            let line = text[..placement.span.start].matches('\n').count();
+            let runs = &mut fields[line.min(lines.len() - 1)];
+            match runs.last_mut() {
+                Some((address, values)) if *address + values.len() == placement.address => {
+                    values.extend(&placement.values);
+                }
+                _ => runs.push((placement.address, placement.values.clone())),
+            }
+        }
+
+        for (number, (line, runs)) in lines.iter().zip(fields).enumerate() {
+            let mut rows = Vec::new();
+            for (address, values) in runs {
+                for (i, chunk) in values.chunks(VALUES_PER_ROW).enumerate() {
+                    let values: Vec<String> = chunk.iter().map(|value| format!("{:>6}", value)).collect();
+                    rows.push(format!("{:>5}  {:<27}", address + i * VALUES_PER_ROW, values.join(" ")));
+                }
+            }
+            // Lines without fields only show their text
+            let mut rows = rows.into_iter();
+            let first = rows.next().unwrap_or_else(|| format!("{:34}", ""));
+            let row = format!("{:>width$}  {}  {}", number + 1, first, line.trim_end_matches('\r'), width = width);
+            output.push_str(row.trim_end());
+            output.push('\n');
+            // Values that don't fit into one row continue below the line
+            for row in rows {
+                output.push_str(format!("{:width$}  {}", "", row, width = width).trim_end());
+                output.push('\n');
+            }
+        }
+    }
+
+    // The location of every placed field, which is the start of the token that placed it
+    pub(super) fn source_map(&self) -> SourceMap {
+        let mut source_map = SourceMap::new();
+        for placement in &self.placements {
+            let text = &self.files[placement.file].text;
+            let start = placement.span.start.min(text.len());
+            let line_start = text[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+            let location = SourceLocation {
+                file:   self.file_name(placement.file),
+                line:   text[..start].matches('\n').count() + 1,
+                column: text[line_start..start].chars().count() + 1,
+            };
+            for address in placement.address..placement.address + placement.values.len() {
+                source_map.insert(address, location.clone());
+            }
+        }
+        source_map
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::vec::IntoIter;
+
+use crate::diagnostic::Severity;
+
+use super::{count, Compiler, Expansion, Expr, SpannedToken, Token};
+
+// Macros may use other macros, but only this deep. This also stops macros that use themselves
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+// A macro defined with
+//
+// .macro NAME param1 param2
+//     ...
+// .endm
+pub(super) struct Macro {
+    params: Vec<String>,
+    body:   Vec<SpannedToken>,
+}
+
+type Tokens = Peekable<IntoIter<SpannedToken>>;
+
+impl Compiler {
+    // Removes all macro definitions from tokens and replaces every use of a macro with its body
+    pub(super) fn expand_macros(&mut self, tokens: Vec<SpannedToken>) -> Vec<SpannedToken> {
+        let mut rest = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match &token.token {
+                Token::Directive(name) if name == "macro" => self.define_macro(token, &mut tokens),
+                Token::Directive(name) if name == "endm" => self.error(&token, ".endm without .macro"),
+                _ => rest.push(token),
+            }
+        }
+
+        self.expand(rest, 0)
+    }
+
+    // Parses the definition after .macro up to .endm
+    fn define_macro(&mut self, directive: SpannedToken, tokens: &mut Tokens) {
+        // The name and the parameters are on the line of .macro
+        let name_token = tokens.next_if(|token| self.same_line(&directive, token));
+        let name = match name_token.as_ref().map(|token| &token.token) {
+            Some(Token::ImmediateLabel(name)) => Some(name.clone()),
+            Some(Token::OpCode(opcode)) => {
+                let message = format!("The macro can't be named like the instruction {}", opcode);
+                self.error(name_token.as_ref().unwrap(), message);
+                None
+            }
+            _ => {
+                self.error(&directive, "Expected the name of the macro after .macro");
+                None
+            }
+        };
+
+        let mut params = Vec::new();
+        let mut last = directive.clone();
+        while let Some(token) = tokens.next_if(|token| self.same_line(&last, token)) {
+            match &token.token {
+                Token::ImmediateLabel(param) => params.push(param.clone()),
+                _ => self.error(&token, "Expected the name of a parameter"),
+            }
+            last = token;
+        }
+
+        let mut body = Vec::new();
+        loop {
+            match tokens.next() {
+                Some(token) if token.is_directive("endm") => break,
+                Some(token) if token.is_directive("macro") => {
+                    self.error(&token, "Macro definitions can't be nested");
+                }
+                Some(SpannedToken { token: Token::EndOfInput, .. }) | None => {
+                    self.error(&directive, "Missing .endm at the end of the macro");
+                    return;
+                }
+                Some(token) => body.push(token),
+            }
+        }
+
+        if let Some(name) = name {
+            if self.macros.contains_key(&name) {
+                self.error(&directive, format!("The macro '{}' is defined more than once", name));
+            }
+            self.macros.insert(name, Macro { params, body });
+        }
+    }
+
+    // Replaces all uses of macros in tokens. Names of macros are only uses where an instruction could be,
+    // not when they are operands
+    fn expand(&mut self, tokens: Vec<SpannedToken>, depth: usize) -> Vec<SpannedToken> {
+        let mut output = Vec::new();
+        let mut pending_operands = 0; // Operands still expected by the last instruction
+        let mut tokens = tokens.into_iter().peekable();
+
+        while let Some(token) = tokens.next() {
+            match &token.token {
+                Token::ImmediateLabel(name) if pending_operands == 0 && self.macros.contains_key(name) => {
+                    let name = name.clone();
+                    self.expand_use(&name, token, &mut tokens, depth, &mut output);
+                    continue;
+                }
+                Token::OpCode(opcode) => pending_operands = opcode.operands().len(),
+                other if other.is_value() => pending_operands = pending_operands.saturating_sub(1),
+                _ => pending_operands = 0,
+            }
+            output.push(token);
+        }
+        output
+    }
+
+    // Writes the body of the macro name used at use_site to output
+    fn expand_use(&mut self, name: &str, use_site: SpannedToken, tokens: &mut Tokens, depth: usize, output: &mut Vec<SpannedToken>) {
+        if depth >= MAX_EXPANSION_DEPTH {
+            // Report at the outermost use instead of listing every level
+            let mut outermost = use_site.expansion.clone();
+            while let Some(parent) = outermost.as_ref().and_then(|expansion| expansion.parent.clone()) {
+                outermost = Some(parent);
+            }
+            let (file, span) = outermost.map(|expansion| (expansion.file, expansion.span)).unwrap_or((use_site.file, use_site.span));
+            let diagnostic = self.diagnostic(Severity::Error, file, span, "Macros are nested too deep")
+                .with_note(self.diagnostic(Severity::Note, use_site.file, use_site.span, format!("maybe '{}' uses itself here", name)));
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+
+        // The arguments follow the name on the same line
+        let param_count = self.macros[name].params.len();
+        let mut args: Vec<SpannedToken> = Vec::new();
+        while args.len() < param_count {
+            let last = args.last().unwrap_or(&use_site);
+            match tokens.next_if(|token| token.token.is_value() && self.same_line(last, token)) {
+                Some(arg) => args.push(arg),
+                None => {
+                    self.error(&use_site, format!("The macro '{}' expects {}, found {}", name, count(param_count, "argument"), args.len()));
+                    return;
+                }
+            }
+        }
+
+        // Labels defined in the macro get a unique name for every use. The # can't be written in source,
+        // so these names don't clash with other labels and are left out of the symbols of the program
+        self.expansions += 1;
+        let suffix = format!("#{}", self.expansions);
+        let definition = &self.macros[name];
+        let locals: HashSet<&String> = definition.body.iter()
+            .filter_map(|token| match &token.token {
+                Token::NamedLabel(label) if !label.ends_with('$') => Some(label),
+                _ => None,
+            })
+            .collect();
+
+        let expansion = Rc::new(Expansion {
+            name:   name.to_string(),
+            span:   use_site.span,
+            file:   use_site.file,
+            parent: use_site.expansion.clone(),
+        });
+
+        let body = definition.body.iter()
+            .map(|token| {
+                let renamed = match &token.token {
+                    Token::ImmediateLabel(label) => {
+                        if let Some(i) = definition.params.iter().position(|param| param == label) {
+                            return args[i].clone(); // Arguments keep their own location
+                        }
+                        if locals.contains(label) {
+                            Token::ImmediateLabel(format!("{}{}", label, suffix))
+                        } else {
+                            token.token.clone()
+                        }
+                    }
+                    Token::Expression(expr) => Token::Expression(expr.replace_labels(&mut |label| {
+                        if let Some(i) = definition.params.iter().position(|param| param == label) {
+                            Expr::from_token(&args[i].token)
+                        } else if locals.contains(&label.to_string()) {
+                            Some(Expr::Label(format!("{}{}", label, suffix)))
+                        } else {
+                            None
+                        }
+                    })),
+                    // Numbered labels are found by position, so they don't need to be renamed
+                    Token::NamedLabel(label) if locals.contains(label) => Token::NamedLabel(format!("{}{}", label, suffix)),
+                    other => other.clone(),
+                };
+                SpannedToken { token: renamed, span: token.span, file: token.file, expansion: Some(expansion.clone()) }
+            })
+            .collect();
+
+        // Macros used in the body are expanded as well
+        let expanded = self.expand(body, depth + 1);
+        output.extend(expanded);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{Compiler, Expr, SpannedToken, Token};
+
+// Where the definitions of every numbered label are, e.g. "1" -> the token indices of all 1$:
+type Numbered = HashMap<String, Vec<usize>>;
+
+impl Compiler {
+    // Renames local labels like .loop to the global label before them, e.g. print.loop,
+    // gives every definition of a numbered label like 1$ its own name and replaces 1f and 1b with
+    // the name of the next or previous definition
+    pub(super) fn resolve_local_labels(&mut self, tokens: Vec<SpannedToken>) -> Vec<SpannedToken> {
+        let mut numbered = Numbered::new();
+        let mut locals = HashSet::new(); // The full names of all local labels
+        let mut scope = String::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if let Token::NamedLabel(name) = &token.token {
+                if let Some(number) = name.strip_suffix('$') {
+                    numbered.entry(number.to_string()).or_default().push(i);
+                } else if name.starts_with('.') {
+                    locals.insert(format!("{}{}", scope, name));
+                } else if token.expansion.is_none() {
+                    scope = name.clone();
+                }
+            }
+        }
+
+        let mut output = Vec::with_capacity(tokens.len());
+        let mut scope = String::new(); // The last global label
+        for (i, mut token) in tokens.iter().cloned().enumerate() {
+            match &token.token {
+                Token::NamedLabel(name) if name.ends_with('$') => {
+                    let number = &name[..name.len() - 1];
+                    let definition = numbered[number].iter().position(|index| *index == i).unwrap();
+                    token.token = Token::NamedLabel(format!("{}${}", number, definition));
+                }
+                Token::NamedLabel(name) if name.starts_with('.') => {
+                    token.token = Token::NamedLabel(format!("{}{}", scope, name));
+                }
+                // Labels in macros stay inside the macro, so they don't start a new scope
+                Token::NamedLabel(name) if token.expansion.is_none() => scope = name.clone(),
+                Token::ImmediateLabel(name) if name.starts_with('.') && (i == 0 || !self.same_line(&tokens[i - 1], &token))
+                    && !locals.contains(&format!("{}{}", scope, name)) => {
+                    // A name at the start of a line that is no local label of the scope is most likely a misspelled
+                    // directive, which check_operands reports
+                    token.token = Token::Directive(name[1..].to_string());
+                }
+                Token::ImmediateLabel(name) => match self.resolve_local_label(name, i, &scope, &numbered) {
+                    Ok(Some(resolved)) => token.token = Token::ImmediateLabel(resolved),
+                    Ok(None) => {}
+                    Err(message) => {
+                        self.error(&token, message);
+                        token.token = Token::ImmediateNumber(0); // Don't report it as unknown label again
+                    }
+                },
+                Token::Expression(expr) => {
+                    let mut errors = Vec::new();
+                    let expr = expr.replace_labels(&mut |name| match self.resolve_local_label(name, i, &scope, &numbered) {
+                        Ok(resolved) => resolved.map(Expr::Label),
+                        Err(message) => {
+                            errors.push(message);
+                            None
+                        }
+                    });
+                    for message in &errors {
+                        self.error(&token, message.clone());
+                    }
+                    token.token = if errors.is_empty() {Token::Expression(expr)} else {Token::ImmediateNumber(0)};
+                }
+                _ => {}
+            }
+            output.push(token);
+        }
+        output
+    }
+
+    // The full name of a used label, or None if it is a global label.
+    // at is the index of the token that uses it, to find numbered labels before or after it
+    fn resolve_local_label(&self, name: &str, at: usize, scope: &str, numbered: &Numbered) -> Result<Option<String>, String> {
+        if name.starts_with('.') {
+            return Ok(Some(format!("{}{}", scope, name)));
+        }
+
+        let (number, forward) = match (name.strip_suffix('f'), name.strip_suffix('b')) {
+            (Some(number), _) => (number, true),
+            (_, Some(number)) => (number, false),
+            _ => return Ok(None),
+        };
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(None);
+        }
+
+        let definitions = numbered.get(number).map(Vec::as_slice).unwrap_or(&[]);
+        let definition = if forward {
+            definitions.iter().position(|index| *index > at)
+        } else {
+            definitions.iter().rposition(|index| *index < at)
+        };
+        match definition {
+            Some(definition) => Ok(Some(format!("{}${}", number, definition))),
+            None if forward => Err(format!("There is no label {}$ after this use of {}", number, name)),
+            None => Err(format!("There is no label {}$ before this use of {}", number, name)),
+        }
+    }
+}
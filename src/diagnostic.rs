@@ -0,0 +1,167 @@
+use std::fmt::Display;
+
+/// How severe a diagnostic is. Only errors make compilation fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The source can't be compiled
+    Error,
+    /// The source compiles, but likely has a mistake
+    Warning,
+    /// Additional information attached to another diagnostic
+    Note,
+}
+
+/// A range of bytes in the source code, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// The first byte of the range
+    pub start:  usize,
+    /// The byte after the range
+    pub end:    usize,
+}
+
+/// A message produced by the compiler, pointing at the part of the source it is about.
+///
+/// Its `Display` implementation renders the message together with the source line
+/// and a caret underline below the span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Whether this is an error, a warning or a note
+    pub severity:   Severity,
+    /// What the diagnostic is about, in one sentence
+    pub message:    String,
+    /// The part of the source the diagnostic points at
+    pub span:       Span,
+    /// The file the span is in, if the source was read from a file
+    pub file:       Option<String>,
+    /// Starts at 1
+    pub line:       usize,
+    /// Starts at 1, counted in characters
+    pub column:     usize,
+    /// Other locations related to this diagnostic, rendered below it
+    pub notes:      Vec<Diagnostic>,
+
+    // The text of the line the span starts in, used for rendering
+    source_line:    String,
+}
+
+impl Span {
+    /// Creates the range from `start` to the exclusive `end`
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error     => write!(f, "error"),
+            Severity::Warning   => write!(f, "warning"),
+            Severity::Note      => write!(f, "note"),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for `span` in `source`, computing its line and column
+    pub fn new(severity: Severity, source: &str, span: Span, message: impl Into<String>) -> Diagnostic {
+        // Clamp the span to the source, so that a span at the end of input is still valid
+        let start = floor_char_boundary(source, span.start);
+        let end = floor_char_boundary(source, span.end.max(start));
+
+        let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[start..].find('\n').map(|i| start + i).unwrap_or(source.len());
+
+        Diagnostic {
+            severity,
+            message:        message.into(),
+            span:           Span::new(start, end),
+            file:           None,
+            line:           source[..start].matches('\n').count() + 1,
+            column:         source[line_start..start].chars().count() + 1,
+            source_line:    source[line_start..line_end].trim_end_matches('\r').to_string(),
+            notes:          Vec::new(),
+        }
+    }
+
+    /// Creates an error for `span` in `source`
+    pub fn error(source: &str, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, source, span, message)
+    }
+
+    /// Creates a warning for `span` in `source`
+    pub fn warning(source: &str, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, source, span, message)
+    }
+
+    /// Creates a note for `span` in `source`, to be attached to another diagnostic with `with_note`
+    pub fn note(source: &str, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Note, source, span, message)
+    }
+
+    /// Attaches a note, e.g. `diagnostic.with_note(Diagnostic::note(source, span, "defined here"))`
+    pub fn with_note(mut self, note: Diagnostic) -> Diagnostic {
+        self.notes.push(note);
+        self
+    }
+
+    /// Sets the file the span is in, so that it is shown in front of the line
+    pub fn in_file(mut self, file: impl Into<String>) -> Diagnostic {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Tests if the diagnostic makes compilation fail
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // The number of characters the caret underline spans, at least 1 and at most until the end of line
+    fn underline_length(&self) -> usize {
+        let remaining = self.source_line.chars().count().saturating_sub(self.column - 1);
+        let spanned = self.span.end - self.span.start;
+        spanned.min(remaining).max(1)
+    }
+}
+
+// Renders the diagnostic with an excerpt of the source, e.g.
+//
+// error: Unknown label 'strat'
+//  --> start.rgm:1:6
+//   |
+// 1 | JUMP strat
+//   |      ^^^^^
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line_number = self.line.to_string();
+        let padding = " ".repeat(line_number.len());
+
+        writeln!(f, "{}: {}", self.severity, self.message)?;
+        match &self.file {
+            Some(file) => writeln!(f, "{}--> {}:{}:{}", padding, file, self.line, self.column)?,
+            None => writeln!(f, "{}--> {}:{}", padding, self.line, self.column)?,
+        }
+        writeln!(f, "{} |", padding)?;
+        writeln!(f, "{} | {}", line_number, self.source_line)?;
+        // Keep tabs in the indentation, so the caret lines up with the excerpt
+        let indent: String = self.source_line.chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' {'\t'} else {' '})
+            .collect();
+        write!(f, "{} | {}{}", padding, indent, "^".repeat(self.underline_length()))?;
+
+        for note in &self.notes {
+            write!(f, "\n{}", note)?;
+        }
+        Ok(())
+    }
+}
+
+// Largest char boundary in source that is not greater than index
+fn floor_char_boundary(source: &str, index: usize) -> usize {
+    let mut index = index.min(source.len());
+    while !source.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
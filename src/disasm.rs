@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::opcode::{Opcode, OperandKind};
+use crate::symbols::{is_label_name, SymbolTable};
+use crate::vm::Value;
+
+// Runs of at least this many printable characters are shown as strings
+const MIN_STRING_LENGTH: usize = 3;
+// Runs of at least this many zeros are skipped with a number label
+const MIN_ZERO_RUN: usize = 8;
+// The number of values in one .data line
+const VALUES_PER_LINE: usize = 8;
+
+// What the disassembler found at an address
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Data,
+    Instruction(Opcode),
+    Operand,
+}
+
+/// Turns memory back into source code that assembles to the same memory.
+///
+/// Code is found by following the control flow from entry, everything else is shown as data.
+/// If symbols are given, their names are used as labels and for address operands
+pub fn disassemble(fields: &[Value], entry: usize, symbols: Option<&SymbolTable>) -> String {
+    let kinds = find_code(fields, entry);
+
+    // The labels of every address. Without symbols, there are none. Names that can't be assembled again are left out
+    let mut labels: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    if let Some(symbols) = symbols {
+        for (name, address) in symbols.in_order().filter(|(name, _)| is_label_name(name)) {
+            labels.entry(address).or_default().push(name);
+        }
+    }
+
+    let mut output = String::new();
+    if entry != 0 {
+        output.push_str(&format!(".entry {}\n", operand(OperandKind::Address, entry as Value, &labels)));
+    }
+    let mut address = 0;
+    while address < fields.len() {
+        for name in labels.get(&address).into_iter().flatten() {
+            output.push_str(&format!("{}:\n", name));
+        }
+
+        match kinds[address] {
+            Field::Instruction(opcode) => {
+                let length = 1 + opcode.operands().len();
+                let mut text = opcode.to_string();
+                for (kind, value) in opcode.operands().iter().zip(&fields[address + 1..address + length]) {
+                    text.push(' ');
+                    text.push_str(&operand(*kind, *value, &labels));
+                }
+                line(&mut output, &text, address, &fields[address..address + length]);
+                address += length;
+            }
+            _ => {
+                // Data continues until the next instruction or label
+                let end = (address + 1..fields.len())
+                    .find(|a| kinds[*a] != Field::Data || labels.contains_key(a))
+                    .unwrap_or(fields.len());
+                data(&mut output, fields, address, end);
+                address = end;
+            }
+        }
+    }
+
+    // Labels at the end of memory
+    for name in labels.range(fields.len()..).flat_map(|(_, names)| names) {
+        output.push_str(&format!("{}:\n", name));
+    }
+    output
+}
+
+// Marks every field reachable from entry as instruction or operand
+fn find_code(fields: &[Value], entry: usize) -> Vec<Field> {
+    let mut kinds = vec![Field::Data; fields.len()];
+    let mut queue = VecDeque::new();
+    queue.push_back(entry);
+
+    while let Some(address) = queue.pop_front() {
+        // Stop at invalid opcodes, fields that were already seen and instructions that don't fit into memory
+        let opcode = match fields.get(address).and_then(|value| Opcode::decode(*value)) {
+            Some(opcode) if kinds[address] == Field::Data => opcode,
+            _ => continue,
+        };
+        let length = 1 + opcode.operands().len();
+        if address + length > fields.len() || kinds[address + 1..address + length].iter().any(|k| *k != Field::Data) {
+            continue;
+        }
+
+        kinds[address] = Field::Instruction(opcode);
+        for kind in &mut kinds[address + 1..address + length] {
+            *kind = Field::Operand;
+        }
+
+        match opcode {
+            Opcode::JUMP => queue.push_back(fields[address + 1] as usize),
+            Opcode::JUMPIFZERO | Opcode::JUMPIFNZERO | Opcode::JUMPLT | Opcode::JUMPGT | Opcode::JUMPIFOVERFLOW => {
+                queue.push_back(fields[address + 1] as usize);
+                queue.push_back(address + length);
+            }
+            Opcode::HALT | Opcode::CJUMP => {} // The target of CJUMP isn't known before running
+            _ => queue.push_back(address + length),
+        }
+    }
+    kinds
+}
+
+// An operand, with addresses replaced by the label at them
+fn operand(kind: OperandKind, value: Value, labels: &BTreeMap<usize, Vec<&str>>) -> String {
+    if kind == OperandKind::Address && value >= 0 {
+        // Of several labels, the last one is directly before the field, like pointer in `end_data: pointer: 0`
+        if let Some(name) = labels.get(&(value as usize)).and_then(|names| names.last()) {
+            return name.to_string();
+        }
+    }
+    value.to_string()
+}
+
+// Writes the fields from start to end as strings, numbers and number labels
+fn data(output: &mut String, fields: &[Value], start: usize, end: usize) {
+    let mut address = start;
+    while address < end {
+        let run = |predicate: &dyn Fn(Value) -> bool| {
+            fields[address..end].iter().take_while(|v| predicate(**v)).count()
+        };
+
+        let printable = run(&|v| is_printable(v));
+        let zeros = run(&|v| v == 0);
+
+        if printable >= MIN_STRING_LENGTH {
+            let text: String = fields[address..address + printable].iter().map(|v| escape(*v)).collect();
+            line(output, &format!(".data \"{}\"", text), address, &[]);
+            address += printable;
+        } else if zeros >= MIN_ZERO_RUN && address + zeros < fields.len() {
+            // Skipped fields are filled with zeros
+            output.push_str(&format!("{}:\n", address + zeros));
+            address += zeros;
+        } else {
+            // Numbers until the next string or long run of zeros
+            let mut line_end = address + 1;
+            while line_end < end && line_end - address < VALUES_PER_LINE {
+                let rest = &fields[line_end..end];
+                let starts_string = rest.iter().take_while(|v| is_printable(**v)).count() >= MIN_STRING_LENGTH;
+                let starts_zeros = rest.iter().take_while(|v| **v == 0).count() >= MIN_ZERO_RUN;
+                if starts_string || starts_zeros {
+                    break;
+                }
+                line_end += 1;
+            }
+            let values: Vec<String> = fields[address..line_end].iter().map(|v| v.to_string()).collect();
+            line(output, &format!(".data {}", values.join(" ")), address, &[]);
+            address = line_end;
+        }
+    }
+}
+
+// Characters that can be written in a string, as they are or as an escape sequence
+fn is_printable(value: Value) -> bool {
+    (0x20..0x7f).contains(&value) || value == '\n' as Value || value == '\t' as Value
+}
+
+// A printable character as it is written in a string
+fn escape(value: Value) -> String {
+    match value as u8 as char {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '"' => "\\\"".to_string(),
+        '\\' => "\\\\".to_string(),
+        c => c.to_string(),
+    }
+}
+
+// Writes one line, with a comment showing the address and the raw fields
+fn line(output: &mut String, text: &str, address: usize, raw: &[Value]) {
+    let raw: Vec<String> = raw.iter().map(|v| v.to_string()).collect();
+    let comment = if raw.is_empty() {format!("{}", address)} else {format!("{}: {}", address, raw.join(" "))};
+    output.push_str(&format!("    {:30} ; {}\n", text, comment));
+}
@@ -0,0 +1,332 @@
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+
+use crate::program::Program;
+use crate::source_map::{SourceLocation, SourceMap};
+use crate::symbols::SymbolTable;
+use crate::vm::{Value, VM};
+
+// The layout of an image, all numbers are little endian:
+//
+// magic        4 bytes, "RGMI"
+// version      u16
+// word size    u8, the bits of a field
+// reserved     u8, always 0
+// entry        u32, the address execution starts at
+// length       u32, the number of fields
+// fields       length times i16
+// sections     each a u8 tag, a u32 length and length bytes, ended by the tag 0
+//
+// Sections with unknown tags are skipped, so newer images can still be loaded
+
+/// The bytes every image starts with
+pub const MAGIC: &[u8; 4] = b"RGMI";
+/// The version of the format written by this crate
+pub const FORMAT_VERSION: u16 = 1;
+
+const WORD_SIZE: u8 = 16;
+
+// Section tags, also used by objects
+pub(crate) const END_SECTION: u8        = 0;
+pub(crate) const SYMBOL_SECTION: u8     = 1; // u32 count, then u16 name length, name, u32 address for each symbol
+pub(crate) const SOURCE_MAP_SECTION: u8 = 2; // u16 file count, then u16 name length, name for each file,
+                                             // u32 count, then u32 address, u16 file, u32 line, u32 column for each field.
+                                             // File 0 is source without a file, file n is the n-th name
+
+/// A program stored in a file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    /// The address execution starts at
+    pub entry:      usize,
+    /// The contents of the memory
+    pub fields:     Vec<Value>,
+    /// The labels of the program, if they were stored
+    pub symbols:    Option<SymbolTable>,
+    /// The source locations of the fields, if they were stored
+    pub source_map: Option<SourceMap>,
+}
+
+/// Why an image could not be read
+#[derive(Debug)]
+pub enum ImageError {
+    /// Reading failed
+    Io(io::Error),
+    /// The data is no image
+    BadMagic,
+    /// The image was written by a newer version of the format
+    UnsupportedVersion(u16),
+    /// The image was written for fields of another size, in bytes
+    UnsupportedWordSize(u8),
+    /// A section or field has invalid contents
+    Malformed(String),
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::Io(error)                   => write!(f, "{}", error),
+            ImageError::BadMagic                    => write!(f, "Not a program image"),
+            ImageError::UnsupportedVersion(version) => write!(f, "Unsupported image version {}", version),
+            ImageError::UnsupportedWordSize(size)   => write!(f, "Unsupported word size of {} bits", size),
+            ImageError::Malformed(message)          => write!(f, "Malformed image: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> ImageError {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            ImageError::Malformed("unexpected end of data".to_string())
+        } else {
+            ImageError::Io(error)
+        }
+    }
+}
+
+/// Tests if data starts like an image
+pub fn is_image(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+impl Image {
+    /// The image of the memory of vm, starting at its current pc
+    pub fn from_vm(vm: &VM) -> Image {
+        Image {
+            entry:      vm.pc,
+            fields:     vm.fields.clone(),
+            symbols:    None,
+            source_map: None,
+        }
+    }
+
+    /// The image of an assembled program, including its symbols and source map
+    pub fn from_program(program: &Program) -> Image {
+        Image {
+            symbols:    Some(program.symbols.clone()),
+            source_map: Some(program.source_map.clone()),
+            ..Image::from_vm(&program.vm)
+        }
+    }
+
+    /// The program stored in the image. Symbols and source locations missing from the image are empty
+    pub fn to_program(&self) -> Program {
+        Program {
+            vm:         self.to_vm(),
+            symbols:    self.symbols.clone().unwrap_or_default(),
+            source_map: self.source_map.clone().unwrap_or_default(),
+            listing:    None,
+        }
+    }
+
+    /// A vm with the memory of the image, ready to start at the entry point
+    pub fn to_vm(&self) -> VM {
+        let mut vm = VM::new();
+        vm.fields = self.fields.clone();
+        vm.pc = self.entry;
+        vm
+    }
+
+    /// Writes the image in the binary format, with the symbols and the source map if they are set
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_header(writer, MAGIC, self.entry, &self.fields)?;
+        if let Some(symbols) = &self.symbols {
+            write_section(writer, SYMBOL_SECTION, &symbol_section(symbols)?)?;
+        }
+        if let Some(source_map) = &self.source_map {
+            write_section(writer, SOURCE_MAP_SECTION, &source_map_section(source_map)?)?;
+        }
+        writer.write_all(&[END_SECTION])
+    }
+
+    /// Reads an image written by `write`. Sections of unknown kinds are skipped
+    pub fn read(reader: &mut dyn Read) -> Result<Image, ImageError> {
+        let (entry, fields) = read_header(reader, MAGIC)?;
+        let mut image = Image { entry, fields, symbols: None, source_map: None };
+        read_sections(reader, &mut |tag, section| {
+            match tag {
+                SYMBOL_SECTION => image.symbols = Some(read_symbols(section)?),
+                SOURCE_MAP_SECTION => image.source_map = Some(read_source_map(section)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(image)
+    }
+}
+
+impl VM {
+    /// Writes the memory and pc of the vm as an image
+    pub fn save_image(&self, writer: &mut dyn Write) -> io::Result<()> {
+        Image::from_vm(self).write(writer)
+    }
+
+    /// Creates a vm from an image, ignoring its symbols
+    pub fn load_image(reader: &mut dyn Read) -> Result<VM, ImageError> {
+        Image::read(reader).map(|image| image.to_vm())
+    }
+}
+
+// Everything before the sections: magic, version, word size, entry and fields
+pub(crate) fn write_header(writer: &mut dyn Write, magic: &[u8; 4], entry: usize, fields: &[Value]) -> io::Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&[WORD_SIZE, 0])?;
+    write_u32(writer, entry)?;
+    write_u32(writer, fields.len())?;
+    for field in fields {
+        writer.write_all(&field.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// The entry and fields of a file that starts with magic
+pub(crate) fn read_header(reader: &mut dyn Read, magic: &[u8; 4]) -> Result<(usize, Vec<Value>), ImageError> {
+    let mut found = [0; 4];
+    reader.read_exact(&mut found)?;
+    if &found != magic {
+        return Err(ImageError::BadMagic);
+    }
+    let version = read_u16(reader)?;
+    if version != FORMAT_VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+    let mut header = [0; 2]; // Word size and reserved byte
+    reader.read_exact(&mut header)?;
+    if header[0] != WORD_SIZE {
+        return Err(ImageError::UnsupportedWordSize(header[0]));
+    }
+
+    let entry = read_u32(reader)? as usize;
+    let length = read_u32(reader)? as usize;
+    let mut fields = Vec::new();
+    for _ in 0..length {
+        fields.push(read_u16(reader)? as Value);
+    }
+    Ok((entry, fields))
+}
+
+// Reads the contents of a section with the tag
+type SectionReader<'a> = dyn FnMut(u8, &mut &[u8]) -> Result<(), ImageError> + 'a;
+
+// Calls read with the tag and contents of every section until the end section
+pub(crate) fn read_sections(reader: &mut dyn Read, read: &mut SectionReader) -> Result<(), ImageError> {
+    loop {
+        let mut tag = [0];
+        reader.read_exact(&mut tag)?;
+        if tag[0] == END_SECTION {
+            return Ok(());
+        }
+        let length = read_u32(reader)? as usize;
+        let mut section = Vec::new();
+        (&mut *reader).take(length as u64).read_to_end(&mut section)?;
+        if section.len() != length {
+            return Err(ImageError::Malformed("unexpected end of data".to_string()));
+        }
+        read(tag[0], &mut section.as_slice())?;
+    }
+}
+
+pub(crate) fn symbol_section(symbols: &SymbolTable) -> io::Result<Vec<u8>> {
+    let mut section = Vec::new();
+    write_u32(&mut section, symbols.len())?;
+    for (name, address) in symbols.in_order() {
+        write_name(&mut section, name)?;
+        write_u32(&mut section, address)?;
+    }
+    Ok(section)
+}
+
+pub(crate) fn source_map_section(source_map: &SourceMap) -> io::Result<Vec<u8>> {
+    let mut files: Vec<&str> = Vec::new();
+    for (_, location) in source_map.iter() {
+        if let Some(file) = &location.file {
+            if !files.contains(&file.as_str()) {
+                files.push(file);
+            }
+        }
+    }
+
+    let mut section = Vec::new();
+    section.write_all(&(files.len() as u16).to_le_bytes())?;
+    for file in &files {
+        write_name(&mut section, file)?;
+    }
+    write_u32(&mut section, source_map.len())?;
+    for (address, location) in source_map.iter() {
+        let file = match &location.file {
+            Some(file) => files.iter().position(|name| name == file).unwrap() + 1,
+            None => 0,
+        };
+        write_u32(&mut section, address)?;
+        section.write_all(&(file as u16).to_le_bytes())?;
+        write_u32(&mut section, location.line)?;
+        write_u32(&mut section, location.column)?;
+    }
+    Ok(section)
+}
+
+pub(crate) fn read_symbols(reader: &mut dyn Read) -> Result<SymbolTable, ImageError> {
+    let mut symbols = SymbolTable::new();
+    for _ in 0..read_u32(reader)? {
+        let name = read_name(reader, "symbol name")?;
+        symbols.insert(name, read_u32(reader)? as usize);
+    }
+    Ok(symbols)
+}
+
+pub(crate) fn read_source_map(reader: &mut dyn Read) -> Result<SourceMap, ImageError> {
+    let mut files = Vec::new();
+    for _ in 0..read_u16(reader)? {
+        files.push(read_name(reader, "file name")?);
+    }
+
+    let mut source_map = SourceMap::new();
+    for _ in 0..read_u32(reader)? {
+        let address = read_u32(reader)? as usize;
+        let file = match read_u16(reader)? as usize {
+            0 => None,
+            n => Some(files.get(n - 1).cloned().ok_or_else(|| ImageError::Malformed(format!("unknown file {} in source map", n)))?),
+        };
+        let line = read_u32(reader)? as usize;
+        let column = read_u32(reader)? as usize;
+        source_map.insert(address, SourceLocation { file, line, column });
+    }
+    Ok(source_map)
+}
+
+// A u16 length followed by the UTF-8 bytes
+pub(crate) fn write_name(writer: &mut dyn Write, name: &str) -> io::Result<()> {
+    writer.write_all(&(name.len() as u16).to_le_bytes())?;
+    writer.write_all(name.as_bytes())
+}
+
+// A name written by write_name, what describes it in errors
+pub(crate) fn read_name(reader: &mut dyn Read, what: &str) -> Result<String, ImageError> {
+    let mut name = vec![0; read_u16(reader)? as usize];
+    reader.read_exact(&mut name)?;
+    String::from_utf8(name).map_err(|_| ImageError::Malformed(format!("{} is not valid UTF-8", what)))
+}
+
+pub(crate) fn write_section(writer: &mut dyn Write, tag: u8, contents: &[u8]) -> io::Result<()> {
+    writer.write_all(&[tag])?;
+    write_u32(writer, contents.len())?;
+    writer.write_all(contents)
+}
+
+pub(crate) fn write_u32(writer: &mut dyn Write, value: usize) -> io::Result<()> {
+    writer.write_all(&(value as u32).to_le_bytes())
+}
+
+pub(crate) fn read_u16(reader: &mut dyn Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u32(reader: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
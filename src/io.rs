@@ -0,0 +1,153 @@
+use std::io::{self, stdin, stdout, Write};
+
+use crate::vm::Value;
+
+/// The input and output used by the vm for `GETC`, `PRINT` and `PRINTC`
+pub trait MachineIo {
+    /// Reads the next character of the input, `None` if the input has ended
+    fn read_char(&mut self) -> io::Result<Option<char>>;
+
+    /// Writes a single character, used by `PRINTC`
+    fn write_char(&mut self, c: char) -> io::Result<()>;
+
+    /// Writes a number followed by a newline, used by `PRINT`
+    fn write_number(&mut self, n: Value) -> io::Result<()> {
+        for c in format!("{}\n", n).chars() {
+            self.write_char(c)?;
+        }
+        Ok(())
+    }
+
+    /// Called when the vm halts
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads from stdin line by line and writes to stdout
+#[derive(Debug, Default)]
+pub struct StdIo {
+    input_buffer: String,
+}
+
+impl StdIo {
+    /// Creates the io with an empty input buffer
+    pub fn new() -> StdIo {
+        StdIo::default()
+    }
+}
+
+impl MachineIo for StdIo {
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        if self.input_buffer.is_empty() { // No characters are bufffered
+            stdin().read_line(&mut self.input_buffer)?;
+        }
+        if self.input_buffer.is_empty() { // Nothing could be read
+            return Ok(None);
+        }
+        Ok(Some(self.input_buffer.remove(0)))
+    }
+
+    fn write_char(&mut self, c: char) -> io::Result<()> {
+        write!(stdout(), "{}", c)
+    }
+
+    fn write_number(&mut self, n: Value) -> io::Result<()> {
+        writeln!(stdout(), "{}", n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        stdout().flush()
+    }
+}
+
+/// Reads from a fixed input string and collects all output in memory
+#[derive(Debug, Clone, Default)]
+pub struct BufferIo {
+    input:      Vec<char>,
+    position:   usize, // Index of the next character to read
+    output:     String,
+}
+
+impl BufferIo {
+    /// Creates the io that reads the characters of `input`
+    pub fn new(input: &str) -> BufferIo {
+        BufferIo {
+            input:      input.chars().collect(),
+            position:   0,
+            output:     String::new(),
+        }
+    }
+
+    /// Everything written so far
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Everything written, without copying it
+    pub fn into_output(self) -> String {
+        self.output
+    }
+
+    /// The part of the input that was not read yet
+    pub fn remaining_input(&self) -> String {
+        self.input[self.position..].iter().collect()
+    }
+}
+
+impl MachineIo for BufferIo {
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        let c = self.input.get(self.position).copied();
+        if c.is_some() {
+            self.position += 1;
+        }
+        Ok(c)
+    }
+
+    fn write_char(&mut self, c: char) -> io::Result<()> {
+        self.output.push(c);
+        Ok(())
+    }
+}
+
+/// Forwards input and output to closures, e.g. to embed the vm into another program:
+///
+/// ```
+/// use registermaschine::{compile, ExitReason, FnIo};
+///
+/// let mut vm = compile("GETC\nADDI 1\nPRINTC\nHALT").unwrap();
+/// let mut output = String::new();
+/// let mut io = FnIo::new(|| Some('a'), |c| output.push(c));
+/// assert_eq!(vm.run(&mut io), Ok(ExitReason::Halted));
+/// assert_eq!(output, "b");
+/// ```
+pub struct FnIo<R, W> {
+    read:   R,
+    write:  W,
+}
+
+impl<R, W> FnIo<R, W>
+where
+    R: FnMut() -> Option<char>,
+    W: FnMut(char),
+{
+    /// `read` is called for every character `GETC` reads, `write` for every character that is written
+    pub fn new(read: R, write: W) -> FnIo<R, W> {
+        FnIo { read, write }
+    }
+}
+
+impl<R, W> MachineIo for FnIo<R, W>
+where
+    R: FnMut() -> Option<char>,
+    W: FnMut(char),
+{
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        Ok((self.read)())
+    }
+
+    fn write_char(&mut self, c: char) -> io::Result<()> {
+        (self.write)(c);
+        Ok(())
+    }
+}
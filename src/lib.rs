@@ -0,0 +1,54 @@
+//! A simulator for a register machine with a single accumulator and one memory,
+//! which holds both the program and its data.
+//!
+//! Programs are written in a small assembly language, translated into memory
+//! contents by [`compile`] and then executed by a [`VM`]. Input and output of the
+//! program go through a [`MachineIo`]:
+//!
+//! ```
+//! use registermaschine::{compile, BufferIo, ExitReason};
+//!
+//! let mut vm = compile("LOADI 6\nMULTIPLYI 7\nSTORE result\nPRINT\nHALT\nresult: 0").unwrap();
+//! let mut io = BufferIo::new("");
+//! assert_eq!(vm.run(&mut io), Ok(ExitReason::Halted));
+//! assert_eq!(vm.fields[8], 42);
+//! assert_eq!(io.output(), "42\n");
+//! ```
+
+/// Translates assembly source into the memory contents of a program
+pub mod compiler;
+/// Errors and warnings of the compiler, rendered with the source line they point at
+pub mod diagnostic;
+/// Turns memory contents back into assembly source
+pub mod disasm;
+/// The binary format that assembled programs are stored in
+pub mod image;
+/// How programs read input and write output
+pub mod io;
+/// Combines separately assembled modules into one program
+pub mod linker;
+/// Modules assembled on their own, to be linked later
+pub mod object;
+/// The instructions of the machine
+pub mod opcode;
+/// An assembled program with the names and source locations of its fields
+pub mod program;
+/// The source locations of the fields of a program
+pub mod source_map;
+/// The addresses of labels
+pub mod symbols;
+/// The machine that executes programs
+pub mod vm;
+
+pub use compiler::{assemble, assemble_object, assemble_with, compile, format_source, lint, CompileOptions};
+pub use diagnostic::{Diagnostic, Severity, Span};
+pub use disasm::disassemble;
+pub use image::{Image, ImageError};
+pub use io::{BufferIo, FnIo, MachineIo, StdIo};
+pub use linker::{link, LinkError};
+pub use object::{Object, Relocation};
+pub use opcode::{opcode_help, Opcode, OpcodeInfo, OperandKind, OPCODES};
+pub use program::Program;
+pub use source_map::{SourceLocation, SourceMap};
+pub use symbols::SymbolTable;
+pub use vm::{ExitReason, Limits, Step, Value, VmError, VM};
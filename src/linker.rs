@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+use crate::object::Object;
+use crate::program::Program;
+use crate::source_map::SourceMap;
+use crate::symbols::SymbolTable;
+use crate::vm::{Value, VM};
+
+/// Why modules could not be linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// No module exports the imported label
+    Unresolved {
+        /// The imported label
+        name:   String,
+        /// The module that imports it
+        module: String,
+    },
+    /// Two modules export the same label
+    DuplicateExport {
+        /// The exported label
+        name:   String,
+        /// The module linked first
+        first:  String,
+        /// The module linked second
+        second: String,
+    },
+    /// Two modules set `.entry`
+    MultipleEntries {
+        /// The module linked first
+        first:  String,
+        /// The module linked second
+        second: String,
+    },
+    /// A relocated field doesn't fit into a field
+    AddressOutOfRange {
+        /// The module of the field
+        module:     String,
+        /// The address of the field, relative to the start of the module
+        address:    usize,
+    },
+    /// The modules don't fit into the addressable memory
+    TooLarge {
+        /// The number of fields of all modules together
+        size: usize,
+    },
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::Unresolved { name, module }              => write!(f, "{} imports '{}', but no module exports it", module, name),
+            LinkError::DuplicateExport { name, first, second }  => write!(f, "'{}' is exported by both {} and {}", name, first, second),
+            LinkError::MultipleEntries { first, second }        => write!(f, "Both {} and {} set the entry point", first, second),
+            LinkError::AddressOutOfRange { module, address }    => write!(f, "The relocated field {} of {} does not fit into a field", address, module),
+            LinkError::TooLarge { size }                        => write!(f, "The linked program has {} fields, more than can be addressed", size),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Places the modules one after another and resolves their imports.
+///
+/// All `objects` are linked, in their order. A module of the `libraries` is only added when it exports a label
+/// that is imported, but not exported by any linked module. Execution starts at the `.entry` of the only module
+/// that sets one, or at the start of the first module
+pub fn link(objects: &[Object], libraries: &[Object]) -> Result<Program, Vec<LinkError>> {
+    let mut modules: Vec<&Object> = objects.iter().collect();
+    let mut errors = Vec::new();
+
+    // Add the library modules for imports that are still missing, which can import further labels
+    let mut i = 0;
+    while i < modules.len() {
+        for name in &modules[i].imports {
+            if modules.iter().any(|module| module.export(name).is_some()) {
+                continue;
+            }
+            match libraries.iter().find(|library| library.export(name).is_some()) {
+                Some(library) => modules.push(library),
+                None => errors.push(LinkError::Unresolved { name: name.clone(), module: modules[i].name.clone() }),
+            }
+        }
+        i += 1;
+    }
+
+    // The start of every module
+    let mut starts = Vec::new();
+    let mut size = 0;
+    for module in &modules {
+        starts.push(size);
+        size += module.fields.len();
+    }
+    if size > Value::MAX as usize + 1 {
+        errors.push(LinkError::TooLarge { size });
+        return Err(errors);
+    }
+
+    // The address of every exported label and the module that exports it
+    let mut exports: HashMap<&str, (usize, &str)> = HashMap::new();
+    for (module, start) in modules.iter().zip(&starts) {
+        for name in &module.exports {
+            let address = match module.export(name) {
+                Some(address) => start + address,
+                None => continue,
+            };
+            match exports.get(name.as_str()) {
+                Some((_, first)) => errors.push(LinkError::DuplicateExport { name: name.clone(), first: first.to_string(), second: module.name.clone() }),
+                None => {
+                    exports.insert(name, (address, &module.name));
+                }
+            }
+        }
+    }
+
+    let mut vm = VM::new();
+    let mut symbols = SymbolTable::new();
+    let mut source_map = SourceMap::new();
+    let mut entry: Option<(usize, &str)> = None;
+    for (module, start) in modules.iter().zip(&starts) {
+        let mut fields = module.fields.clone();
+        for relocation in &module.relocations {
+            let offset = match &relocation.symbol {
+                Some(symbol) => match exports.get(symbol.as_str()) {
+                    Some((address, _)) => *address,
+                    None => continue, // Reported as unresolved
+                },
+                None => *start,
+            };
+            let relocated = fields.get(relocation.address)
+                .and_then(|field| Value::try_from(offset).ok().and_then(|offset| field.checked_add(offset)));
+            match relocated {
+                Some(value) => fields[relocation.address] = value,
+                None => errors.push(LinkError::AddressOutOfRange { module: module.name.clone(), address: relocation.address }),
+            }
+        }
+        vm.fields.extend(fields);
+
+        // Labels of different modules may have the same name, then the first one is kept
+        for (name, address) in module.symbols.in_order() {
+            if symbols.get(name).is_none() {
+                symbols.insert(name, start + address);
+            }
+        }
+        for (address, location) in module.source_map.iter() {
+            source_map.insert(start + address, location.clone());
+        }
+        if let Some(address) = module.entry {
+            match entry {
+                Some((_, first)) => errors.push(LinkError::MultipleEntries { first: first.to_string(), second: module.name.clone() }),
+                None => entry = Some((start + address, &module.name)),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    vm.pc = entry.map(|(address, _)| address).unwrap_or(0);
+    Ok(Program { vm, symbols, source_map, listing: None })
+}
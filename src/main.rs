@@ -1,34 +1,359 @@
-mod vm;
-mod compiler;
+use std::{fs::{self, File}, env, path::{Path, PathBuf}, time::Duration};
 
-use std::{fs::File, env, io::Read};
+use registermaschine::{assemble_object, assemble_with, disassemble, format_source, image, link, lint, object, opcode_help, CompileOptions, ExitReason, Image, Limits, Object, Program, StdIo};
 
+const USAGE: &str = "Usage: registermaschine [--max-steps N] [--time-limit MS] [ASSEMBLER OPTIONS] FILE...
+       registermaschine --disassemble [ASSEMBLER OPTIONS] FILE...
+       registermaschine --listing [ASSEMBLER OPTIONS] FILE
+       registermaschine --output IMAGE [ASSEMBLER OPTIONS] FILE...
+       registermaschine --object OBJECT [ASSEMBLER OPTIONS] FILE
+       registermaschine fmt [--check] [--ignore-case] FILE...
+       registermaschine lint [-I DIR] [--ignore-case] FILE...
+       registermaschine --opcodes
+FILE is either source code, an image created with --output or an object created with --object.
+Several files, objects and libraries are linked into one program.
+Assembler options:
+  -I DIR         adds DIR to the directories searched for .include files
+  -L DIR         links the modules of DIR that export labels the program imports
+  --ignore-case  accepts instructions in lower case
+fmt rewrites the files in a canonical layout. With --check, it only lists the files that aren't formatted.
+lint warns about likely mistakes, like code that is never executed.
+Exit codes: 0 on success, 1 for errors in the files (and for unformatted files or warnings of fmt and lint),
+2 for wrong arguments, 3 for runtime errors and 4 if --max-steps or --time-limit stopped the program.";
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+// The exit codes of the process
+const EXIT_ERROR: i32 = 1;              // A file has errors, or could not be read or written
+const EXIT_USAGE: i32 = 2;              // The arguments are wrong
+const EXIT_RUNTIME_ERROR: i32 = 3;      // The program stopped with an error
+const EXIT_BUDGET_EXHAUSTED: i32 = 4;   // The program was stopped by the limits
+
+// The parsed command line
+struct Options {
+    files:          Vec<String>,
+    limits:         Limits,
+    disassemble:    bool,           // Print the assembled program instead of running it
+    listing:        bool,           // Print the source next to the assembled fields instead of running it
+    output:         Option<String>, // Write the assembled program to this image instead of running it
+    object:         Option<String>, // Write the file as object to link later instead of running it
+    libraries:      Vec<PathBuf>,   // Directories with modules to link
+    compile:        CompileOptions,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut files = Vec::new();
+    let mut limits = Limits::default();
+    let mut disassemble = false;
+    let mut listing = false;
+    let mut output = None;
+    let mut object = None;
+    let mut libraries = Vec::new();
+    let mut compile = CompileOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--max-steps" => {
+                limits.max_steps = Some(parse_number(arg, iter.next())?);
+            }
+            "--time-limit" => {
+                limits.time_limit = Some(Duration::from_millis(parse_number(arg, iter.next())?));
+            }
+            "--disassemble" => disassemble = true,
+            "--listing" => {
+                listing = true;
+                compile.listing = true;
+            }
+            "--output" => {
+                output = Some(iter.next().ok_or("Expected a file after --output")?.clone());
+            }
+            "--object" => {
+                object = Some(iter.next().ok_or("Expected a file after --object")?.clone());
+            }
+            "-I" | "--include-path" => {
+                let path = iter.next().ok_or_else(|| format!("Expected a directory after {}", arg))?;
+                compile.include_paths.push(PathBuf::from(path));
+            }
+            "-L" | "--library" => {
+                let path = iter.next().ok_or_else(|| format!("Expected a directory after {}", arg))?;
+                libraries.push(PathBuf::from(path));
+            }
+            "--ignore-case" => compile.ignore_case = true,
+            _ if !arg.starts_with("--") => files.push(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    if files.is_empty() {
+        return Err("Expected a file".to_string());
+    }
+    if files.len() > 1 && (listing || object.is_some()) {
+        return Err("Expected a single file for --listing and --object".to_string());
+    }
+    Ok(Options { files, limits, disassemble, listing, output, object, libraries, compile })
+}
+
+// Parses the value following an option
+fn parse_number(option: &str, value: Option<&String>) -> Result<u64, String> {
+    value.and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("Expected a number after {}", option))
+}
 
-    if args.len() != 2 { // Illegal argument count
-        println!("Error: Expected 1 argument");
+// The program in a single file, which is either source or an image
+fn load_program(file: &str, options: &Options) -> Result<Program, String> {
+    let content = fs::read(file).map_err(|err| err.to_string())?;
+
+    if image::is_image(&content) { // Load an assembled program
+        if options.listing {
+            return Err("Error: A listing can only be created from source code, not from an image".to_string());
+        }
+        Image::read(&mut content.as_slice())
+            .map(|image| image.to_program())
+            .map_err(|error| format!("Error while loading image: {}", error))
+    } else { // Compile the source
+        let source = String::from_utf8_lossy(&content);
+        assemble_with(&source, Some(Path::new(file)), &options.compile).map_err(|diagnostics| {
+            for diagnostic in diagnostics {
+                eprintln!("{}\n", diagnostic);
+            }
+            format!("Error while compiling {}", file)
+        })
+    }
+}
+
+// The module in a file, which is either source or an object
+fn load_object(file: &Path, options: &CompileOptions) -> Result<Object, String> {
+    let content = fs::read(file).map_err(|err| format!("{}: {}", file.display(), err))?;
+
+    if object::is_object(&content) {
+        let mut object = Object::read(&mut content.as_slice())
+            .map_err(|error| format!("Error while loading object {}: {}", file.display(), error))?;
+        object.name = file.display().to_string();
+        Ok(object)
     } else {
-        let file = File::open(&args[1]); // Open the specified file
-        if let Result::Ok(mut file) = file { // File was opened
-            // Read the file
-            let mut content = String::new();
-            file.read_to_string(&mut content).expect("Error: Could not read file");
+        let source = String::from_utf8_lossy(&content);
+        assemble_object(&source, Some(file), options).map_err(|diagnostics| {
+            for diagnostic in diagnostics {
+                eprintln!("{}\n", diagnostic);
+            }
+            format!("Error while compiling {}", file.display())
+        })
+    }
+}
 
-            // Compile the source
-            let vm = compiler::compile(&content);
-            
-            // If compilation was successful
-            if let Some(mut vm) = vm {
-                vm.run();
-            } else {
-                println!("Error while compiling vm");
+// The modules of a library directory: all sources (.rgm) and objects (.rgmo), sorted by name
+fn load_library(directory: &Path, options: &CompileOptions) -> Result<Vec<Object>, String> {
+    let entries = fs::read_dir(directory).map_err(|err| format!("{}: {}", directory.display(), err))?;
+    let mut files: Vec<PathBuf> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension == "rgm" || extension == "rgmo"))
+        .collect();
+    files.sort();
+    files.iter().map(|file| load_object(file, options)).collect()
+}
+
+// Links all files together with the libraries they need
+fn link_program(options: &Options) -> Result<Program, String> {
+    if options.listing {
+        return Err("Error: A listing can only be created for a single file that isn't linked".to_string());
+    }
+    let objects = options.files.iter()
+        .map(|file| load_object(Path::new(file), &options.compile))
+        .collect::<Result<Vec<Object>, String>>()?;
+    let mut libraries = Vec::new();
+    for directory in &options.libraries {
+        libraries.extend(load_library(directory, &options.compile)?);
+    }
+
+    link(&objects, &libraries).map_err(|errors| {
+        for error in errors {
+            eprintln!("error: {}", error);
+        }
+        "Error while linking".to_string()
+    })
+}
+
+// Formats the files given after fmt, returns the exit code
+fn format_files(args: &[String]) -> i32 {
+    let mut check = false;
+    let mut options = CompileOptions::default();
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--ignore-case" => options.ignore_case = true,
+            _ if !arg.starts_with("--") => files.push(arg),
+            _ => {
+                eprintln!("Error: Unexpected argument '{}'\n{}", arg, USAGE);
+                return EXIT_USAGE;
             }
-        } else if let Result::Err(err) = file {
-            println!("{}", err);
         }
     }
+    if files.is_empty() {
+        eprintln!("Error: Expected a file\n{}", USAGE);
+        return EXIT_USAGE;
+    }
+
+    let mut code = 0;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                code = EXIT_ERROR;
+                continue;
+            }
+        };
+        let formatted = match format_source(&source, &options) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    eprintln!("{}\n", diagnostic.in_file(file.clone()));
+                }
+                code = EXIT_ERROR;
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", file);
+            code = EXIT_ERROR;
+        } else if let Err(err) = fs::write(file, formatted) {
+            eprintln!("{}: {}", file, err);
+            code = EXIT_ERROR;
+        }
+    }
+    code
+}
+
+// Checks the files given after lint for likely mistakes, returns the exit code
+fn lint_files(args: &[String]) -> i32 {
+    let mut options = CompileOptions::default();
+    let mut files = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-I" | "--include-path" => match iter.next() {
+                Some(path) => options.include_paths.push(PathBuf::from(path)),
+                None => {
+                    eprintln!("Error: Expected a directory after {}\n{}", arg, USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--ignore-case" => options.ignore_case = true,
+            _ if !arg.starts_with("--") => files.push(arg),
+            _ => {
+                eprintln!("Error: Unexpected argument '{}'\n{}", arg, USAGE);
+                return EXIT_USAGE;
+            }
+        }
+    }
+    if files.is_empty() {
+        eprintln!("Error: Expected a file\n{}", USAGE);
+        return EXIT_USAGE;
+    }
+
+    let mut code = 0;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                code = EXIT_ERROR;
+                continue;
+            }
+        };
+        let diagnostics = lint(&source, Some(Path::new(file)), &options).unwrap_or_else(|errors| errors);
+        if !diagnostics.is_empty() {
+            code = EXIT_ERROR;
+        }
+        for diagnostic in diagnostics {
+            eprintln!("{}\n", diagnostic);
+        }
+    }
+    code
 }
 
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    std::process::exit(run(&args));
+}
 
+// Runs the command line, returns the exit code
+fn run(args: &[String]) -> i32 {
+    if args.first().is_some_and(|arg| arg == "fmt") { // Format source files instead of running them
+        return format_files(&args[1..]);
+    }
+    if args.first().is_some_and(|arg| arg == "lint") { // Check source files instead of running them
+        return lint_files(&args[1..]);
+    }
+
+    if args.iter().any(|arg| arg == "--opcodes") { // Print the supported instructions
+        print!("{}", opcode_help());
+        return 0;
+    }
+
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(error) => { // Illegal arguments
+            eprintln!("Error: {}\n{}", error, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    if let Some(output) = &options.object { // Assemble a module to link later
+        let result = load_object(Path::new(&options.files[0]), &options.compile)
+            .and_then(|object| {
+                File::create(output).and_then(|mut file| object.write(&mut file))
+                    .map_err(|error| format!("Error while writing object: {}", error))
+            });
+        if let Err(error) = result {
+            eprintln!("{}", error);
+            return EXIT_ERROR;
+        }
+        return 0;
+    }
+
+    // Several files, libraries and objects have to be linked
+    let linking = options.files.len() > 1 || !options.libraries.is_empty()
+        || fs::read(&options.files[0]).is_ok_and(|content| object::is_object(&content));
+    let program = if linking {
+        link_program(&options)
+    } else {
+        load_program(&options.files[0], &options)
+    };
+    let mut program = match program {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}", error);
+            return EXIT_ERROR;
+        }
+    };
+
+    if let Some(listing) = &program.listing {
+        print!("{}", listing);
+    } else if options.disassemble {
+        print!("{}", disassemble(&program.vm.fields, program.vm.pc, Some(&program.symbols)));
+    } else if let Some(output) = options.output {
+        let result = File::create(&output).and_then(|mut file| Image::from_program(&program).write(&mut file));
+        if let Err(error) = result {
+            eprintln!("Error while writing image: {}", error);
+            return EXIT_ERROR;
+        }
+    } else {
+        program.vm.limits = options.limits;
+        match program.vm.run(&mut StdIo::new()) {
+            Ok(ExitReason::BudgetExhausted { pc, steps }) => {
+                eprintln!("\nStopped at {} after {} steps: limit exhausted", program.describe(pc), steps);
+                return EXIT_BUDGET_EXHAUSTED;
+            }
+            Ok(_) => println!("\n"),
+            Err(error) => {
+                eprintln!("Runtime error at {}: {}", program.describe(error.pc()), error);
+                return EXIT_RUNTIME_ERROR;
+            }
+        }
+    }
+    0
+}
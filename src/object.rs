@@ -0,0 +1,139 @@
+use std::io::{self, Read, Write};
+
+use crate::image::{self, ImageError, END_SECTION, SOURCE_MAP_SECTION, SYMBOL_SECTION};
+use crate::source_map::SourceMap;
+use crate::symbols::SymbolTable;
+use crate::vm::Value;
+
+// An object is laid out like an image, but starts with "RGMO" and has the entry u32::MAX if the module
+// doesn't set one. Besides the symbols and the source map, it has these sections:
+const EXPORT_SECTION: u8        = 3; // u32 count, then u16 name length, name for each exported label
+const IMPORT_SECTION: u8        = 4; // The same for each imported label
+const RELOCATION_SECTION: u8    = 5; // u32 count, then u32 address, u32 import for each relocation.
+                                     // Import 0 is the start of the module, import n is the n-th imported label
+
+/// The bytes every object starts with
+pub const OBJECT_MAGIC: &[u8; 4] = b"RGMO";
+
+const NO_ENTRY: usize = u32::MAX as usize;
+
+/// A field whose value depends on where the linker places the modules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// The address of the field, relative to the start of the module
+    pub address:    usize,
+    /// The import whose address is added to the field, `None` adds the start of the module
+    pub symbol:     Option<String>,
+}
+
+/// A module that was assembled on its own. The linker places it in memory and connects it to other modules
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    /// Names the module in link errors, usually its file. Not stored in object files
+    pub name:           String,
+    /// The contents of the module. Addresses in the fields are relative to the start of the module
+    pub fields:         Vec<Value>,
+    /// The address set with `.entry`, relative to the start of the module
+    pub entry:          Option<usize>,
+    /// All labels of the module
+    pub symbols:        SymbolTable,
+    /// Labels that other modules can import
+    pub exports:        Vec<String>,
+    /// Labels that other modules define
+    pub imports:        Vec<String>,
+    /// The fields the linker has to adjust
+    pub relocations:    Vec<Relocation>,
+    /// The source locations of the fields, relative to the start of the module
+    pub source_map:     SourceMap,
+}
+
+/// Tests if data starts like an object
+pub fn is_object(data: &[u8]) -> bool {
+    data.starts_with(OBJECT_MAGIC)
+}
+
+impl Object {
+    /// The address of an exported label, relative to the start of the module
+    pub fn export(&self, name: &str) -> Option<usize> {
+        if self.exports.iter().any(|export| export == name) {
+            self.symbols.get(name)
+        } else {
+            None
+        }
+    }
+
+    /// Writes the object in the binary format. Fails if a relocation refers to a label that isn't imported
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        image::write_header(writer, OBJECT_MAGIC, self.entry.unwrap_or(NO_ENTRY), &self.fields)?;
+        image::write_section(writer, SYMBOL_SECTION, &image::symbol_section(&self.symbols)?)?;
+        image::write_section(writer, SOURCE_MAP_SECTION, &image::source_map_section(&self.source_map)?)?;
+        image::write_section(writer, EXPORT_SECTION, &names_section(&self.exports)?)?;
+        image::write_section(writer, IMPORT_SECTION, &names_section(&self.imports)?)?;
+
+        let mut section = Vec::new();
+        image::write_u32(&mut section, self.relocations.len())?;
+        for relocation in &self.relocations {
+            let import = match &relocation.symbol {
+                Some(symbol) => self.imports.iter().position(|import| import == symbol)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is relocated, but not imported", symbol)))? + 1,
+                None => 0,
+            };
+            image::write_u32(&mut section, relocation.address)?;
+            image::write_u32(&mut section, import)?;
+        }
+        image::write_section(writer, RELOCATION_SECTION, &section)?;
+
+        writer.write_all(&[END_SECTION])
+    }
+
+    /// Reads an object written by `write`. Its `name` is left empty
+    pub fn read(reader: &mut dyn Read) -> Result<Object, ImageError> {
+        let (entry, fields) = image::read_header(reader, OBJECT_MAGIC)?;
+        let entry = if entry == NO_ENTRY {None} else {Some(entry)};
+        let mut object = Object { fields, entry, ..Object::default() };
+
+        let mut relocations = Vec::new(); // Read once the imports are known
+        image::read_sections(reader, &mut |tag, section| {
+            match tag {
+                SYMBOL_SECTION => object.symbols = image::read_symbols(section)?,
+                SOURCE_MAP_SECTION => object.source_map = image::read_source_map(section)?,
+                EXPORT_SECTION => object.exports = read_names(section)?,
+                IMPORT_SECTION => object.imports = read_names(section)?,
+                RELOCATION_SECTION => {
+                    for _ in 0..image::read_u32(section)? {
+                        relocations.push((image::read_u32(section)? as usize, image::read_u32(section)? as usize));
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        for (address, import) in relocations {
+            let symbol = match import {
+                0 => None,
+                n => Some(object.imports.get(n - 1).cloned()
+                    .ok_or_else(|| ImageError::Malformed(format!("unknown import {} in relocation", n)))?),
+            };
+            object.relocations.push(Relocation { address, symbol });
+        }
+        Ok(object)
+    }
+}
+
+fn names_section(names: &[String]) -> io::Result<Vec<u8>> {
+    let mut section = Vec::new();
+    image::write_u32(&mut section, names.len())?;
+    for name in names {
+        image::write_name(&mut section, name)?;
+    }
+    Ok(section)
+}
+
+fn read_names(reader: &mut dyn Read) -> Result<Vec<String>, ImageError> {
+    let mut names = Vec::new();
+    for _ in 0..image::read_u32(reader)? {
+        names.push(image::read_name(reader, "label name")?);
+    }
+    Ok(names)
+}
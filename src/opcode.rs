@@ -0,0 +1,186 @@
+use std::{convert::TryFrom, fmt::Display};
+
+use crate::vm::Value;
+
+/// The kind of an operand that follows an opcode in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// The operand is used as a value
+    Immediate,
+    /// The operand is the address of a field, or the target of a jump
+    Address,
+}
+
+/// Everything known about an opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    /// The opcode itself
+    pub opcode:         Opcode,
+    /// The name of the opcode in source code
+    pub mnemonic:       &'static str,
+    /// The value the opcode is stored as
+    pub code:           Value,
+    /// The operands that follow the opcode, in order
+    pub operands:       &'static [OperandKind],
+    /// How long the instruction takes to execute
+    pub cycles:         u32,
+    /// What the instruction does, in one sentence
+    pub description:    &'static str,
+}
+
+// Declares the Opcode enum and the OPCODES table from one list.
+// The codes are given by the order of the list, so OPCODES can be indexed by code
+macro_rules! opcodes {
+    ($($name:ident ($($kind:ident),*) $cycles:literal $description:literal,)*) => {
+        /// The instructions of the machine, stored in memory by their numeric value.
+        ///
+        /// If an opcode ends with I, it takes an immediate argument
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(i16)]
+        #[allow(clippy::upper_case_acronyms)]
+        pub enum Opcode {
+            $(#[doc = $description] $name,)*
+        }
+
+        /// All opcodes, ordered by their code
+        pub static OPCODES: &[OpcodeInfo] = &[
+            $(OpcodeInfo {
+                opcode:         Opcode::$name,
+                mnemonic:       stringify!($name),
+                code:           Opcode::$name as Value,
+                operands:       &[$(OperandKind::$kind),*],
+                cycles:         $cycles,
+                description:    $description,
+            },)*
+        ];
+    };
+}
+
+opcodes! {
+    NOOP            ()                    1 "Does nothing",
+    // Load and store for the accumulator
+    LOADI           (Immediate)           1 "Loads the operand into the accumulator",
+    LOAD            (Address)             2 "Loads the field into the accumulator",
+    LOADIND         ()                    2 "Loads the field whose address is in the accumulator",
+    STORE           (Address)             2 "Stores the accumulator in the field",
+    STOREIND        (Address)             3 "Stores the accumulator in the field whose address is stored in the operand field",
+    // Arithmetic instructions
+    ADDI            (Immediate)           1 "Adds the operand to the accumulator",
+    ADD             (Address)             2 "Adds the field to the accumulator",
+    SUBTRACTI       (Immediate)           1 "Subtracts the operand from the accumulator",
+    SUBTRACT        (Address)             2 "Subtracts the field from the accumulator",
+    MULTIPLYI       (Immediate)           3 "Multiplies the accumulator with the operand",
+    MULTIPLY        (Address)             4 "Multiplies the accumulator with the field",
+    DIVIDEI         (Immediate)           4 "Divides the accumulator by the operand",
+    DIVIDE          (Address)             5 "Divides the accumulator by the field",
+    REMAINDER       (Address)             5 "Stores the remainder of the accumulator divided by the field",
+    REMAINDERI      (Immediate)           4 "Stores the remainder of the accumulator divided by the operand",
+    NEGATE          ()                    1 "Negates the accumulator",
+    // Bit arithmetic
+    SHIFTL          (Address)             2 "Shifts the accumulator left by the field",
+    SHIFTLI         (Immediate)           1 "Shifts the accumulator left by the operand",
+    SHIFTR          (Address)             2 "Shifts the accumulator right by the field",
+    SHIFTRI         (Immediate)           1 "Shifts the accumulator right by the operand",
+    AND             (Address)             2 "Binary and of the accumulator and the field",
+    ANDI            (Immediate)           1 "Binary and of the accumulator and the operand",
+    OR              (Address)             2 "Binary or of the accumulator and the field",
+    ORI             (Immediate)           1 "Binary or of the accumulator and the operand",
+    XOR             (Address)             2 "Binary xor of the accumulator and the field",
+    XORI            (Immediate)           1 "Binary xor of the accumulator and the operand",
+    NOT             ()                    1 "Inverts the bits of the accumulator",
+    // Logical instructions, the accumulator becomes 1 if the condition holds, otherwise 0
+    EQUALI          (Immediate)           1 "Tests if the accumulator equals the operand",
+    EQUAL           (Address)             2 "Tests if the accumulator equals the field",
+    GREATERI        (Immediate)           1 "Tests if the operand is greater than the accumulator",
+    GREATER         (Address)             2 "Tests if the field is greater than the accumulator",
+    LESSI           (Immediate)           1 "Tests if the operand is less than the accumulator",
+    LESS            (Address)             2 "Tests if the field is less than the accumulator",
+    // Control flow
+    JUMP            (Address)             1 "Jumps to the operand",
+    CJUMP           ()                    1 "Jumps to the address in the accumulator",
+    JUMPIFZERO      (Address)             1 "Jumps to the operand if the accumulator is 0",
+    JUMPIFNZERO     (Address)             1 "Jumps to the operand if the accumulator is not 0",
+    JUMPLT          (Address)             1 "Jumps to the operand if the accumulator is less than 0",
+    JUMPGT          (Address)             1 "Jumps to the operand if the accumulator is greater than 0",
+    JUMPIFOVERFLOW  (Address)             1 "Jumps to the operand if an arithmetic instruction overflowed, and clears the overflow",
+    // Move fields
+    MOVE            (Address, Address)    3 "Copies the first field to the second field",
+    MOVEI           (Immediate, Address)  2 "Stores the first operand in the second field",
+    MOVEIND         (Address)             3 "Copies the field to the field whose address is in the accumulator",
+
+    HALT            ()                    1 "Ends the execution",
+    PRINT           ()                    1 "Prints the accumulator as a number, with newline",
+    PRINTC          ()                    1 "Prints the accumulator as a character, without newline",
+    GETC            ()                    1 "Reads the next character of the input into the accumulator",
+}
+
+impl Opcode {
+    /// Everything known about this opcode
+    pub fn info(self) -> &'static OpcodeInfo {
+        &OPCODES[self as usize]
+    }
+
+    /// The name of this opcode in source code
+    pub fn mnemonic(self) -> &'static str {
+        self.info().mnemonic
+    }
+
+    /// The operands that follow this opcode, in order
+    pub fn operands(self) -> &'static [OperandKind] {
+        self.info().operands
+    }
+
+    /// How many cycles the instruction takes to execute
+    pub fn cycles(self) -> u32 {
+        self.info().cycles
+    }
+
+    /// Finds the opcode with the given mnemonic
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        OPCODES.iter().find(|info| info.mnemonic == mnemonic).map(|info| info.opcode)
+    }
+
+    /// Like [`Opcode::from_mnemonic`], but also finds `halt` or `Halt`
+    pub fn from_mnemonic_ignore_case(mnemonic: &str) -> Option<Opcode> {
+        OPCODES.iter().find(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic)).map(|info| info.opcode)
+    }
+
+    /// Finds the opcode stored as value
+    pub fn decode(value: Value) -> Option<Opcode> {
+        if value < 0 {
+            return None;
+        }
+        OPCODES.get(value as usize).map(|info| info.opcode)
+    }
+}
+
+/// Decodes an opcode, returning the value back if it is not a valid opcode
+impl TryFrom<Value> for Opcode {
+    type Error = Value;
+
+    fn try_from(value: Value) -> Result<Opcode, Value> {
+        Opcode::decode(value).ok_or(value)
+    }
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.mnemonic())
+    }
+}
+
+/// A table of all opcodes with their operands, cycles and descriptions
+pub fn opcode_help() -> String {
+    let mut help = String::new();
+    for info in OPCODES {
+        let operands: Vec<&str> = info.operands.iter()
+            .map(|kind| match kind {
+                OperandKind::Immediate  => "value",
+                OperandKind::Address    => "address",
+            })
+            .collect();
+        let signature = format!("{} {}", info.mnemonic, operands.join(" "));
+        help.push_str(&format!("{:3} {:30} {} cycles  {}\n", info.code, signature, info.cycles, info.description));
+    }
+    help
+}
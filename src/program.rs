@@ -0,0 +1,28 @@
+use crate::source_map::SourceMap;
+use crate::symbols::SymbolTable;
+use crate::vm::VM;
+
+/// An assembled program: its memory together with the names and source locations of its fields
+#[derive(Debug)]
+pub struct Program {
+    /// The vm with the program loaded, ready to start at the entry point
+    pub vm:         VM,
+    /// The addresses of all labels
+    pub symbols:    SymbolTable,
+    /// The source location of every field
+    pub source_map: SourceMap,
+    /// The listing, if `CompileOptions::listing` was set
+    pub listing:    Option<String>,
+}
+
+impl Program {
+    /// Describes an address by the closest label before it and the source line it was assembled from,
+    /// like `loop+3 (summe.rgm:11)`
+    pub fn describe(&self, address: usize) -> String {
+        let symbolic = self.symbols.symbolic(address);
+        match self.source_map.get(address) {
+            Some(location) => format!("{} ({})", symbolic, location),
+            None => symbolic,
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// A position in the source code of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The file, `None` for source that wasn't read from a file
+    pub file:   Option<String>,
+    /// The line, starting at 1
+    pub line:   usize,
+    /// The column in characters, starting at 1
+    pub column: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+/// The source location every field of a program was assembled from.
+///
+/// Fields that were placed by a macro belong to the use of the macro, fields that were only
+/// skipped, like the gaps before a `.org`, have no location
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    locations: BTreeMap<usize, SourceLocation>,
+}
+
+impl SourceMap {
+    /// Creates a map without locations
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    /// Sets the location of the field at address, replacing an earlier one
+    pub fn insert(&mut self, address: usize, location: SourceLocation) {
+        self.locations.insert(address, location);
+    }
+
+    /// The location of the field at address
+    pub fn get(&self, address: usize) -> Option<&SourceLocation> {
+        self.locations.get(&address)
+    }
+
+    /// All addresses with their locations, sorted by address
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &SourceLocation)> {
+        self.locations.iter().map(|(address, location)| (*address, location))
+    }
+
+    /// The number of fields with a location
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Tests if no field has a location
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
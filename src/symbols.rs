@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use crate::opcode::Opcode;
+
+/// The addresses of the labels of a program.
+///
+/// Tables are equal if they have the same symbols, and the names at every address were defined in the same order
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols:    BTreeMap<String, usize>,
+    defined:    Vec<String>, // The names in the order they were defined
+}
+
+impl SymbolTable {
+    /// Creates a table without symbols
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Defines name at address, replacing an earlier definition
+    pub fn insert(&mut self, name: impl Into<String>, address: usize) {
+        let name = name.into();
+        if self.symbols.insert(name.clone(), address).is_some() {
+            self.defined.retain(|defined| *defined != name);
+        }
+        self.defined.push(name);
+    }
+
+    /// The address of name
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.symbols.get(name).copied()
+    }
+
+    /// All names defined at address, in the order they were defined
+    pub fn names_at(&self, address: usize) -> Vec<&str> {
+        self.in_order().filter(|(_, a)| *a == address).map(|(name, _)| name).collect()
+    }
+
+    /// The address relative to the closest label at or before it, like `loop+3`.
+    /// Addresses before all labels are shown as numbers
+    pub fn symbolic(&self, address: usize) -> String {
+        // The first name of several at the same address
+        let closest = self.iter()
+            .filter(|(_, a)| *a <= address)
+            .min_by_key(|(_, a)| address - a);
+        match closest {
+            Some((name, a)) if a == address => name.to_string(),
+            Some((name, a)) => format!("{}+{}", name, address - a),
+            None => address.to_string(),
+        }
+    }
+
+    /// All symbols, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.symbols.iter().map(|(name, address)| (name.as_str(), *address))
+    }
+
+    /// All symbols in the order they were defined
+    pub fn in_order(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.defined.iter().map(move |name| (name.as_str(), self.symbols[name]))
+    }
+
+    // All symbols sorted by address, names at the same address in the order they were defined
+    fn by_address(&self) -> Vec<(&str, usize)> {
+        let mut symbols: Vec<(&str, usize)> = self.in_order().collect();
+        symbols.sort_by_key(|(_, address)| *address); // Stable, so the order of definition is kept
+        symbols
+    }
+
+    /// The number of symbols
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Tests if the table has no symbols
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+impl PartialEq for SymbolTable {
+    fn eq(&self, other: &SymbolTable) -> bool {
+        self.by_address() == other.by_address()
+    }
+}
+
+impl Eq for SymbolTable {}
+
+/// Tests if name can be written as a label in source code. Names the assembler generates for numbered labels
+/// like `1$`, for labels in macros and for local labels before the first global label can't
+pub fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_');
+    first && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.') && Opcode::from_mnemonic(name).is_none()
+}
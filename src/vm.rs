@@ -1,166 +1,151 @@
-use std::{fmt::Display, io::{stdin, Stdin}};
+use std::{convert::TryFrom, fmt::Display, time::{Duration, Instant}};
 
+use crate::io::MachineIo;
+
+pub use crate::opcode::{Opcode, OperandKind};
+
+/// The content of a single field and of the accumulator
 pub type Value = i16;
 
+// How often the time limit is checked, in executed instructions
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Why the vm stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// A HALT instruction was executed
+    Halted,
+    /// `run_for` or `run_until` stopped before the program halted
+    Paused,
+    /// The step or time limit was reached before the instruction at `pc`, after `steps` instructions in total
+    BudgetExhausted {
+        /// The address of the next instruction, where the program can be resumed
+        pc:     usize,
+        /// The number of instructions the vm executed so far
+        steps:  u64,
+    },
+}
 
-// If an opcode ends with I, it takes an immediate argument
-#[repr(i16)]
-pub enum Opcode {
-    NOOP = 0, // Does Nothing, is 0
-    LOADI, LOAD, LOADIND, STORE, STOREIND,  // load and store for the accumulator
-                                            // LOADIND loads value from field address stored
-    // Arithmetic instructions.
-    ADDI, ADD, SUBTRACTI, SUBTRACT,
-    MULTIPLYI, MULTIPLY, DIVIDEI, DIVIDE,
-    REMAINDER, REMAINDERI, NEGATE,
-    // Bit Arithmetic
-    SHIFTL, SHIFTLI, SHIFTR, SHIFTRI,
-    AND, ANDI, OR, ORI, XOR, XORI,
-    NOT,
-    // Logical instructions
-    EQUALI, EQUAL, GREATERI, GREATER,
-    LESSI, LESS,
-    // Control flow
-    JUMP, CJUMP, // JUMP jumps to argument, CJUMP jumps to field address stored
-    JUMPIFZERO, JUMPIFNZERO, JUMPLT, JUMPGT,
-    JUMPIFOVERFLOW,
-    // Move fields
-    MOVE, // 2 Arguments, moves from first to second
-    MOVEI, // Moves first (immediate) argument to second argument
-    MOVEIND, // Moves from first argument to field address stored
-
-    HALT, // Ends the Execution of the MV
-    PRINT, // Prints the Value of the Accumulator, with newline
-    PRINTC, // Prints the Value of the Accumulator as char, no newline
-    GETC, // Stores the next ascii character from the console in the accumulator
+/// Bounds for running the vm, `None` means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The number of instructions the vm may execute, counted over its whole lifetime
+    pub max_steps:  Option<u64>,
+    /// How long the vm may run, counted from the start of each call to `run`, `run_for` or `run_until`
+    pub time_limit: Option<Duration>,
 }
 
-impl Into<Opcode> for Value {
+/// What a single instruction did when it was executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// The address of the executed instruction
+    pub pc:         usize,
+    /// The opcode of the executed instruction
+    pub opcode:     Opcode,
+    /// The operands read after the opcode
+    pub operands:   Vec<Value>,
+    /// The fields written, with their new value
+    pub writes:     Vec<(usize, Value)>,
+    /// Whether the instruction was HALT
+    pub halted:     bool,
+}
+
+/// Errors that stop the execution of the vm. The `pc` of each error is the address of the failing instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /// The value at `pc` is no opcode
+    InvalidOpcode {
+        /// The address of the field
+        pc:     usize,
+        /// The value of the field
+        value:  Value,
+    },
+    /// The instruction accessed a field outside of the memory
+    AddressOutOfBounds {
+        /// The address of the instruction
+        pc:     usize,
+        /// The address that was accessed
+        addr:   Value,
+    },
+    /// DIVIDE, DIVIDEI, REMAINDER or REMAINDERI divided by zero
+    DivisionByZero {
+        /// The address of the instruction
+        pc: usize,
+    },
+    /// The instruction or its operands are outside of the memory
+    PcOutOfRange {
+        /// The address of the instruction
+        pc: usize,
+    },
+    /// GETC could not read a character
+    InputError {
+        /// The address of the instruction
+        pc:         usize,
+        /// Why reading failed
+        message:    String,
+    },
+    /// PRINT or PRINTC could not write
+    OutputError {
+        /// The address of the instruction
+        pc:         usize,
+        /// Why writing failed
+        message:    String,
+    },
+}
 
-    fn into(self) -> Opcode {
+impl Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            x if Opcode::GETC           as Value == x => Opcode::GETC,
-            x if Opcode::SHIFTL         as Value == x => Opcode::SHIFTL,
-            x if Opcode::SHIFTLI        as Value == x => Opcode::SHIFTLI,
-            x if Opcode::SHIFTR         as Value == x => Opcode::SHIFTR,
-            x if Opcode::SHIFTRI        as Value == x => Opcode::SHIFTRI,
-            x if Opcode::AND            as Value == x => Opcode::AND,
-            x if Opcode::ANDI           as Value == x => Opcode::ANDI,
-            x if Opcode::OR             as Value == x => Opcode::OR,
-            x if Opcode::ORI            as Value == x => Opcode::ORI,
-            x if Opcode::XOR            as Value == x => Opcode::XOR,
-            x if Opcode::XORI           as Value == x => Opcode::XORI,
-            x if Opcode::NOT            as Value == x => Opcode::NOT, 
-            x if Opcode::NEGATE         as Value == x => Opcode::NEGATE,
-            x if Opcode::REMAINDER      as Value == x => Opcode::REMAINDER,
-            x if Opcode::REMAINDERI     as Value == x => Opcode::REMAINDERI,
-            x if Opcode::STOREIND       as Value == x => Opcode::STOREIND,
-            x if Opcode::JUMPGT         as Value == x => Opcode::JUMPGT,
-            x if Opcode::JUMPLT         as Value == x => Opcode::JUMPLT,
-            x if Opcode::NOOP           as Value == x => Opcode::NOOP,
-            x if Opcode::PRINTC         as Value == x => Opcode::PRINTC,
-            x if Opcode::PRINT          as Value == x => Opcode::PRINT,
-            x if Opcode::LOAD           as Value == x => Opcode::LOAD,
-            x if Opcode::LOADI          as Value == x => Opcode::LOADI,
-            x if Opcode::LOADIND        as Value == x => Opcode::LOADIND,
-            x if Opcode::STORE          as Value == x => Opcode::STORE,
-            x if Opcode::ADD            as Value == x => Opcode::ADD,
-            x if Opcode::ADDI           as Value == x => Opcode::ADDI,
-            x if Opcode::SUBTRACT       as Value == x => Opcode::SUBTRACT,
-            x if Opcode::SUBTRACTI      as Value == x => Opcode::SUBTRACTI,
-            x if Opcode::MULTIPLY       as Value == x => Opcode::MULTIPLY,
-            x if Opcode::MULTIPLYI      as Value == x => Opcode::MULTIPLYI,
-            x if Opcode::DIVIDE         as Value == x => Opcode::DIVIDE,
-            x if Opcode::DIVIDEI        as Value == x => Opcode::DIVIDEI,
-            x if Opcode::EQUAL          as Value == x => Opcode::EQUAL,
-            x if Opcode::EQUALI         as Value == x => Opcode::EQUALI,
-            x if Opcode::GREATER        as Value == x => Opcode::GREATER,
-            x if Opcode::GREATERI       as Value == x => Opcode::GREATERI,
-            x if Opcode::LESS           as Value == x => Opcode::LESS,
-            x if Opcode::LESSI          as Value == x => Opcode::LESSI,
-            x if Opcode::JUMP           as Value == x => Opcode::JUMP,
-            x if Opcode::CJUMP          as Value == x => Opcode::CJUMP,
-            x if Opcode::JUMPIFZERO     as Value == x => Opcode::JUMPIFZERO,
-            x if Opcode::JUMPIFNZERO    as Value == x => Opcode::JUMPIFNZERO,
-            x if Opcode::JUMPIFOVERFLOW as Value == x => Opcode::JUMPIFOVERFLOW,
-            x if Opcode::MOVE           as Value == x => Opcode::MOVE,
-            x if Opcode::MOVEI          as Value == x => Opcode::MOVEI,
-            x if Opcode::MOVEIND        as Value == x => Opcode::MOVEIND,
-            x if Opcode::HALT           as Value == x => Opcode::HALT,
-            x => {
-                panic!("Unknown Opcode {}", x);
-            }
+            VmError::InvalidOpcode { pc, value }        => write!(f, "Invalid opcode {} at {}", value, pc),
+            VmError::AddressOutOfBounds { pc, addr }    => write!(f, "Address {} out of bounds at {}", addr, pc),
+            VmError::DivisionByZero { pc }              => write!(f, "Division by zero at {}", pc),
+            VmError::PcOutOfRange { pc }                => write!(f, "Instruction at {} reaches past the end of memory", pc),
+            VmError::InputError { pc, message }         => write!(f, "Could not read input at {}: {}", pc, message),
+            VmError::OutputError { pc, message }        => write!(f, "Could not write output at {}: {}", pc, message),
         }
     }
 }
 
-impl Display for Opcode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match self {
-            Opcode::GETC            => "GETC",
-            Opcode::SHIFTL          => "SHIFTL",
-            Opcode::SHIFTLI         => "SHIFTLI",
-            Opcode::SHIFTR          => "SHIFTR",
-            Opcode::SHIFTRI         => "SHIFTRI",
-            Opcode::AND             => "AND",
-            Opcode::ANDI            => "ANDI",
-            Opcode::OR              => "OR",
-            Opcode::ORI             => "ORI",
-            Opcode::XOR             => "XOR",
-            Opcode::XORI            => "XORI",
-            Opcode::NOT             => "NOT",
-            Opcode::NEGATE          => "NEGATE",
-            Opcode::REMAINDER       => "REMAINDER",
-            Opcode::REMAINDERI      => "REMAINDERI",
-            Opcode::JUMPLT          => "JUMPLT",
-            Opcode::JUMPGT          => "JUMPGT",
-            Opcode::NOOP            => "NOOP",
-            Opcode::PRINTC          => "PRINTC",
-            Opcode::JUMP            => "JUMP",
-            Opcode::PRINT           => "PRINT",
-            Opcode::LOADI           => "LOADI",
-            Opcode::LOAD            => "LOAD",
-            Opcode::LOADIND         => "LOADIND",
-            Opcode::STORE           => "STORE",
-            Opcode::STOREIND        => "STOREIND",
-            Opcode::ADDI            => "ADDI",
-            Opcode::ADD             => "ADD",
-            Opcode::SUBTRACTI       => "SUBTRACTI",
-            Opcode::SUBTRACT        => "SUBTRACT",
-            Opcode::MULTIPLYI       => "MULTIPLYI",
-            Opcode::MULTIPLY        => "MULTIPLY",
-            Opcode::DIVIDEI         => "DIVIDEI",
-            Opcode::DIVIDE          => "DIVIDE",
-            Opcode::EQUALI          => "EQUALI",
-            Opcode::EQUAL           => "EQUAL",
-            Opcode::GREATERI        => "GREATERI",
-            Opcode::GREATER         => "GREATER",
-            Opcode::LESSI           => "LESSI",
-            Opcode::LESS            => "LESS",
-            Opcode::CJUMP           => "CJUMP",
-            Opcode::JUMPIFZERO      => "JUMPIFZERO",
-            Opcode::JUMPIFNZERO     => "JUMPIFNZERO",
-            Opcode::JUMPIFOVERFLOW  => "JUMPIFOVERFLOW",
-            Opcode::MOVE            => "MOVE",
-            Opcode::MOVEI           => "MOVEI",
-            Opcode::MOVEIND         => "MOVEIND",
-            Opcode::HALT            => "HALT", 
+impl VmError {
+    /// The address of the instruction that failed
+    pub fn pc(&self) -> usize {
+        match self {
+            VmError::InvalidOpcode { pc, .. } | VmError::AddressOutOfBounds { pc, .. } => *pc,
+            VmError::DivisionByZero { pc } | VmError::PcOutOfRange { pc } => *pc,
+            VmError::InputError { pc, .. } | VmError::OutputError { pc, .. } => *pc,
         }
-    )
     }
 }
 
+impl std::error::Error for VmError {}
+
+/// The state of the machine: its registers and its memory
+#[derive(Debug)]
 pub struct VM {
     // Register
+    /// The register that all arithmetic works on
     pub accumulator:    Value,
+    /// The address of the next instruction
     pub pc:             usize,
+    /// Set by arithmetic instructions that overflowed, cleared by JUMPIFOVERFLOW
+    pub overflow:       bool,
 
     // Fields
+    /// The memory, which holds both the program and its data
     pub fields:         Vec<Value>,
 
-    // Input buffer
-    input_buffer: String,
-    stdin: Stdin
+    /// Bounds for `run`, `run_for` and `run_until`
+    pub limits:         Limits,
+    /// The number of instructions executed so far
+    pub steps:          u64,
+    /// The sum of the cycles of all executed instructions
+    pub cycles:         u64,
+
+    // The address of the instruction that is currently executed
+    instruction:        usize,
+    // Operands read and fields written by the current instruction
+    operands:           Vec<Value>,
+    writes:             Vec<(usize, Value)>,
 }
 
 impl Display for VM {
@@ -169,237 +154,408 @@ impl Display for VM {
     }
 }
 
+impl Default for VM {
+    fn default() -> VM {
+        VM::new()
+    }
+}
+
 impl VM {
+    /// Creates a vm with empty memory
     pub fn new() -> VM {
         VM {
             accumulator:    0,
             pc:             0,
+            overflow:       false,
             fields:         Vec::new(),
-            input_buffer: String::new(),
-            stdin: stdin()
+            limits:         Limits::default(),
+            steps:          0,
+            cycles:         0,
+            instruction:    0,
+            operands:       Vec::new(),
+            writes:         Vec::new(),
         }
     }
 
+    /// Appends a field to the end of the memory
     pub fn write_value(&mut self, val: Value) {
         self.fields.push(val);
     }
 
-    pub fn run(&mut self) {
+    /// Runs until HALT is executed, the limits are exhausted or an error occurs.
+    ///
+    /// On an error, `pc` is reset to the failing instruction and all other state is kept as it was
+    pub fn run(&mut self, io: &mut dyn MachineIo) -> Result<ExitReason, VmError> {
+        self.run_with(io, None, |_, _| false)
+    }
+
+    /// Executes at most `steps` instructions, stopping early if the program halts
+    pub fn run_for(&mut self, io: &mut dyn MachineIo, steps: usize) -> Result<ExitReason, VmError> {
+        self.run_with(io, Some(steps), |_, _| false)
+    }
+
+    /// Executes instructions until the program halts or `predicate` returns true for an executed step
+    pub fn run_until<P>(&mut self, io: &mut dyn MachineIo, predicate: P) -> Result<ExitReason, VmError>
+    where
+        P: FnMut(&VM, &Step) -> bool,
+    {
+        self.run_with(io, None, predicate)
+    }
+
+    // Steps until the program halts, the limits are reached, remaining steps were executed
+    // or the predicate returns true
+    fn run_with<P>(&mut self, io: &mut dyn MachineIo, mut remaining: Option<usize>, mut predicate: P) -> Result<ExitReason, VmError>
+    where
+        P: FnMut(&VM, &Step) -> bool,
+    {
+        let deadline = self.limits.time_limit.map(|limit| Instant::now() + limit);
+        let started_at = self.steps;
+
         loop {
-            let instruction = self.next_value().into();
-            
-            // println!("Executing {}", instruction);
-            
-            match instruction {
-                Opcode::NOOP => {}
-                Opcode::GETC => {
-                    if self.input_buffer.is_empty() { // No characters are bufffered
-                        match self.stdin.read_line(&mut self.input_buffer) {
-                            Err(error) => {
-                                panic!("{}", error)
-                            }
-                            _ => {
-                            }
-                        }
-                    }
-                    let character = self.input_buffer.remove(0);
-                    self.accumulator = character as i16;
-                }
-                Opcode::SHIFTL => {
-                    let field = self.next_value();
-                    self.accumulator <<= self.fields[field as usize];
-                }
-                Opcode::SHIFTLI => {
-                    let arg = self.next_value();
-                    self.accumulator <<= arg;
-                }
-                Opcode::SHIFTR => {
-                    let field = self.next_value();
-                    self.accumulator >>= self.fields[field as usize];
-                }
-                Opcode::SHIFTRI => {
-                    let arg = self.next_value();
-                    self.accumulator >>= arg;
-                }
-                Opcode::AND => {
-                    let field = self.next_value();
-                    self.accumulator &= self.fields[field as usize];
-                }
-                Opcode::ANDI => {
-                    let arg = self.next_value();
-                    self.accumulator &= arg;
-                }
-                Opcode::OR => {
-                    let field = self.next_value();
-                    self.accumulator |= self.fields[field as usize];
-                }
-                Opcode::ORI => {
-                    let arg = self.next_value();
-                    self.accumulator |=  arg;
-                }
-                Opcode::XOR => {
-                    let field = self.next_value();
-                    self.accumulator ^= self.fields[field as usize];
-                }
-                Opcode::XORI => {
-                    let arg = self.next_value();
-                    self.accumulator ^= arg;
-                }
-                Opcode::NOT => {
-                    self.accumulator = !self.accumulator;
-                }
-                Opcode::PRINTC => {
-                    print!("{}", char::from_u32(self.accumulator as u32).unwrap_or(char::REPLACEMENT_CHARACTER));
-                }
-                Opcode::PRINT => {
-                    println!("{}", self.accumulator);
-                },
-                Opcode::LOAD => { // Store Value in field stored in argument field
-                    let field = self.next_value();
-                    self.accumulator = self.fields[field as usize];
-                }
-                Opcode::LOADI => { // Store immediate value
-                    let arg = self.next_value();
-                    self.accumulator = arg;
-                },
-                Opcode::LOADIND => { // Store value at field stored in accumulator
-                    self.accumulator = self.fields[self.accumulator as usize];
-                },
-                Opcode::STORE => {
-                    let field = self.next_value();
-                    self.fields[field as usize] = self.accumulator;
-                },
-                Opcode::STOREIND => {
-                    let address_field = self.next_value() as usize;
-                    let address = self.fields[address_field] as usize;
-                    self.fields[address] = self.accumulator;
-                }
-                Opcode::REMAINDERI => {
-                    let arg = self.next_value();
-                    self.accumulator = self.accumulator.wrapping_rem(arg);
+            if remaining == Some(0) {
+                return Ok(ExitReason::Paused);
+            }
+            if self.budget_exhausted(deadline, started_at) {
+                return Ok(ExitReason::BudgetExhausted { pc: self.pc, steps: self.steps });
+            }
+
+            let step = self.step(io)?;
+            if step.halted {
+                return Ok(ExitReason::Halted);
+            }
+            if predicate(self, &step) {
+                return Ok(ExitReason::Paused);
+            }
+            remaining = remaining.map(|n| n - 1);
+        }
+    }
+
+    fn budget_exhausted(&self, deadline: Option<Instant>, started_at: u64) -> bool {
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.steps >= max_steps {
+                return true;
+            }
+        }
+        // Reading the clock is expensive, so it is only done every few instructions
+        if let Some(deadline) = deadline {
+            if (self.steps - started_at).is_multiple_of(TIME_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Executes the single instruction at `pc` and reports what it did.
+    ///
+    /// HALT leaves `pc` at the HALT instruction, so stepping a halted vm halts again.
+    /// Errors leave the vm in the same state as [`VM::run`]
+    pub fn step(&mut self, io: &mut dyn MachineIo) -> Result<Step, VmError> {
+        self.instruction = self.pc;
+        self.operands.clear();
+        self.writes.clear();
+
+        match self.execute(io) {
+            Ok(opcode) => {
+                self.steps += 1;
+                self.cycles += opcode.cycles() as u64;
+                Ok(Step {
+                    pc:         self.instruction,
+                    opcode,
+                    operands:   std::mem::take(&mut self.operands),
+                    writes:     std::mem::take(&mut self.writes),
+                    halted:     opcode == Opcode::HALT,
+                })
+            },
+            Err(error) => {
+                self.pc = self.instruction;
+                Err(error)
+            }
+        }
+    }
+
+    // Executes the instruction at pc and returns its opcode
+    fn execute(&mut self, io: &mut dyn MachineIo) -> Result<Opcode, VmError> {
+        let value = self.fetch()?;
+        let instruction = Opcode::try_from(value)
+            .map_err(|value| VmError::InvalidOpcode { pc: self.instruction, value })?;
+
+        // println!("Executing {}", instruction);
+
+        match instruction {
+            Opcode::NOOP => {}
+            Opcode::GETC => {
+                match io.read_char() {
+                    Ok(Some(character)) => self.accumulator = character as i16,
+                    Ok(None) => return Err(self.input_error("End of input".to_string())),
+                    Err(error) => return Err(self.input_error(error.to_string())),
                 }
-                Opcode::REMAINDER => {
-                    let field = self.next_value();
-                    self.accumulator = self.accumulator.wrapping_rem(self.fields[field as usize]);
+            }
+            Opcode::SHIFTL => {
+                let arg = self.load_operand()?;
+                self.accumulator = self.accumulator.wrapping_shl(arg as u32);
+            }
+            Opcode::SHIFTLI => {
+                let arg = self.next_value()?;
+                self.accumulator = self.accumulator.wrapping_shl(arg as u32);
+            }
+            Opcode::SHIFTR => {
+                let arg = self.load_operand()?;
+                self.accumulator = self.accumulator.wrapping_shr(arg as u32);
+            }
+            Opcode::SHIFTRI => {
+                let arg = self.next_value()?;
+                self.accumulator = self.accumulator.wrapping_shr(arg as u32);
+            }
+            Opcode::AND => {
+                self.accumulator &= self.load_operand()?;
+            }
+            Opcode::ANDI => {
+                self.accumulator &= self.next_value()?;
+            }
+            Opcode::OR => {
+                self.accumulator |= self.load_operand()?;
+            }
+            Opcode::ORI => {
+                self.accumulator |= self.next_value()?;
+            }
+            Opcode::XOR => {
+                self.accumulator ^= self.load_operand()?;
+            }
+            Opcode::XORI => {
+                self.accumulator ^= self.next_value()?;
+            }
+            Opcode::NOT => {
+                self.accumulator = !self.accumulator;
+            }
+            Opcode::PRINTC => {
+                let character = char::from_u32(self.accumulator as u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+                io.write_char(character).map_err(|error| self.output_error(error))?;
+            }
+            Opcode::PRINT => {
+                io.write_number(self.accumulator).map_err(|error| self.output_error(error))?;
+            },
+            Opcode::LOAD => { // Store Value in field stored in argument field
+                self.accumulator = self.load_operand()?;
+            }
+            Opcode::LOADI => { // Store immediate value
+                self.accumulator = self.next_value()?;
+            },
+            Opcode::LOADIND => { // Store value at field stored in accumulator
+                self.accumulator = self.load(self.accumulator)?;
+            },
+            Opcode::STORE => {
+                let field = self.next_value()?;
+                self.store(field, self.accumulator)?;
+            },
+            Opcode::STOREIND => {
+                let address = self.load_operand()?;
+                self.store(address, self.accumulator)?;
+            }
+            Opcode::REMAINDERI => {
+                let arg = self.next_value()?;
+                self.remainder(arg)?;
+            }
+            Opcode::REMAINDER => {
+                let arg = self.load_operand()?;
+                self.remainder(arg)?;
+            }
+            Opcode::ADDI => {
+                let arg = self.next_value()?;
+                self.arithmetic(self.accumulator.overflowing_add(arg));
+            },
+            Opcode::ADD => {
+                let arg = self.load_operand()?;
+                self.arithmetic(self.accumulator.overflowing_add(arg));
+            },
+            Opcode::SUBTRACTI => {
+                let arg = self.next_value()?;
+                self.arithmetic(self.accumulator.overflowing_sub(arg));
+            },
+            Opcode::SUBTRACT => {
+                let arg = self.load_operand()?;
+                self.arithmetic(self.accumulator.overflowing_sub(arg));
+            },
+            Opcode::NEGATE => {
+                self.arithmetic(self.accumulator.overflowing_neg());
+            }
+            Opcode::MULTIPLYI => {
+                let arg = self.next_value()?;
+                self.arithmetic(self.accumulator.overflowing_mul(arg));
+            },
+            Opcode::MULTIPLY => {
+                let arg = self.load_operand()?;
+                self.arithmetic(self.accumulator.overflowing_mul(arg));
+            },
+            Opcode::DIVIDEI => {
+                let arg = self.next_value()?;
+                self.divide(arg)?;
+            },
+            Opcode::DIVIDE => {
+                let arg = self.load_operand()?;
+                self.divide(arg)?;
+            },
+            Opcode::EQUALI => {
+                let arg = self.next_value()?;
+                self.accumulator = if self.accumulator == arg {1} else {0};
+            },
+            Opcode::EQUAL => {
+                let arg = self.load_operand()?;
+                self.accumulator = if self.accumulator == arg {1} else {0};
+            },
+            Opcode::GREATERI => { // Test if argument is greater than accumulator
+                let arg = self.next_value()?;
+                self.accumulator = if self.accumulator < arg {1} else {0};
+            },
+            Opcode::GREATER => {
+                let arg = self.load_operand()?;
+                self.accumulator = if self.accumulator < arg {1} else {0};
+            },
+            Opcode::LESSI => { // Tet if argument is less than accumulator
+                let arg = self.next_value()?;
+                self.accumulator = if self.accumulator > arg {1} else {0};
+            },
+            Opcode::LESS => {
+                let arg = self.load_operand()?;
+                self.accumulator = if self.accumulator > arg {1} else {0};
+            },
+            Opcode::JUMP => {
+                let arg = self.next_value()?;
+                self.jump(arg)?;
+            },
+            Opcode::CJUMP => {
+                self.jump(self.accumulator)?;
+            },
+            Opcode::JUMPIFZERO => {
+                let arg = self.next_value()?;
+                if self.accumulator == 0 {
+                    self.jump(arg)?;
                 }
-                Opcode::ADDI => {
-                    let arg = self.next_value();
-                    self.accumulator = arg.wrapping_add(self.accumulator);
-                },
-                Opcode::ADD => {
-                    let field = self.next_value();
-                    self.accumulator = self.fields[field as usize].wrapping_add(self.accumulator);
-                },
-                Opcode::SUBTRACTI => {
-                    let arg = self.next_value();
-                    self.accumulator = self.accumulator.wrapping_sub(arg);
-                },
-                Opcode::SUBTRACT => {
-                    let field = self.next_value();
-                    self.accumulator -= self.accumulator.wrapping_sub(self.fields[field as usize]);
-                },
-                Opcode::NEGATE => {
-                    self.accumulator = self.accumulator.wrapping_neg();
+            },
+            Opcode::JUMPIFNZERO => {
+                let arg = self.next_value()?;
+                if self.accumulator != 0 {
+                    self.jump(arg)?;
                 }
-                Opcode::MULTIPLYI => {
-                    let arg = self.next_value();
-                    self.accumulator = arg.wrapping_mul(self.accumulator);
-                },
-                Opcode::MULTIPLY => {
-                    let field = self.next_value();
-                    self.accumulator = self.fields[field as usize].wrapping_mul(self.accumulator);
-                },
-                Opcode::DIVIDEI => {
-                    let arg = self.next_value();
-                    self.accumulator = self.accumulator.wrapping_div(arg);
-                },
-                Opcode::DIVIDE => {
-                    let field = self.next_value();
-                    self.accumulator /= self.accumulator.wrapping_div(self.fields[field as usize]);
-                },
-                Opcode::EQUALI => {
-                    let arg = self.next_value();
-                    self.accumulator = if self.accumulator == arg {1} else {0};
-                },
-                Opcode::EQUAL => {
-                    let field = self.next_value();
-                    self.accumulator = if self.accumulator == self.fields[field as usize] {1} else {0};
-                },
-                Opcode::GREATERI => { // Test if argument is greater than accumulator
-                    let arg = self.next_value();
-                    self.accumulator = if self.accumulator < arg {1} else {0};
-                },
-                Opcode::GREATER => {
-                    let field = self.next_value();
-                    self.accumulator = if self.accumulator < self.fields[field as usize] {1} else {0};
-                },
-                Opcode::LESSI => { // Tet if argument is less than accumulator
-                    let arg = self.next_value();
-                    self.accumulator = if self.accumulator > arg {1} else {0};
-                },
-                Opcode::LESS => {
-                    let field = self.next_value();
-                    self.accumulator = if self.accumulator > self.fields[field as usize] {1} else {0};
-                },
-                Opcode::JUMP => {
-                    let arg = self.next_value();
-                    self.pc = arg as usize;
-                },
-                Opcode::CJUMP => {
-                    self.pc = self.accumulator as usize;
-                },
-                Opcode::JUMPIFZERO => {
-                    let arg = self.next_value();
-                    if self.accumulator == 0 {
-                        self.pc = arg as usize;
-                    }
-                },
-                Opcode::JUMPIFNZERO => {
-                    let arg = self.next_value();
-                    if self.accumulator != 0 {
-                        self.pc = arg as usize;
-                    }
-                },
-                Opcode::JUMPLT => {
-                    let arg = self.next_value();
-                    if self.accumulator < 0 {
-                        self.pc = arg as usize;
-                    }
+            },
+            Opcode::JUMPLT => {
+                let arg = self.next_value()?;
+                if self.accumulator < 0 {
+                    self.jump(arg)?;
                 }
-                Opcode::JUMPGT => {
-                    let arg = self.next_value();
-                    if self.accumulator > 0 {
-                        self.pc = arg as usize;
-                    }
+            }
+            Opcode::JUMPGT => {
+                let arg = self.next_value()?;
+                if self.accumulator > 0 {
+                    self.jump(arg)?;
                 }
-                Opcode::JUMPIFOVERFLOW => {
-                    panic!("Overflow detection not implemented yet.");
-                },
-                Opcode::MOVE => {
-                    let from = self.next_value();
-                    let to = self.next_value();
-                    self.fields[to as usize] = self.fields[from as usize];
-                },
-                Opcode::MOVEI => {
-                    let value = self.next_value();
-                    let to = self.next_value();
-                    self.fields[to as usize] = value;
-                },
-                Opcode::MOVEIND => {
-                    let from = self.next_value();
-                    self.fields[self.accumulator as usize] = self.fields[from as usize];
-                },
-                Opcode::HALT => {
-                    println!("\n");
-                    break;
-                },
             }
+            Opcode::JUMPIFOVERFLOW => {
+                let arg = self.next_value()?;
+                if self.overflow {
+                    self.overflow = false;
+                    self.jump(arg)?;
+                }
+            },
+            Opcode::MOVE => {
+                let value = self.load_operand()?;
+                let to = self.next_value()?;
+                self.store(to, value)?;
+            },
+            Opcode::MOVEI => {
+                let value = self.next_value()?;
+                let to = self.next_value()?;
+                self.store(to, value)?;
+            },
+            Opcode::MOVEIND => {
+                let value = self.load_operand()?;
+                self.store(self.accumulator, value)?;
+            },
+            Opcode::HALT => {
+                io.flush().map_err(|error| self.output_error(error))?;
+                self.pc = self.instruction;
+            },
         }
+
+        Ok(instruction)
     }
 
-    fn next_value(&mut self) -> Value {
-        let value = self.fields[self.pc];
+    // Reads the value at pc and moves pc to the next field
+    fn fetch(&mut self) -> Result<Value, VmError> {
+        let value = *self.fields.get(self.pc).ok_or(VmError::PcOutOfRange { pc: self.instruction })?;
         self.pc += 1;
-        value
+        Ok(value)
+    }
+
+    // Reads the next operand of the current instruction
+    fn next_value(&mut self) -> Result<Value, VmError> {
+        let value = self.fetch()?;
+        self.operands.push(value);
+        Ok(value)
+    }
+
+    // Reads the next value as an address and loads the value stored there
+    fn load_operand(&mut self) -> Result<Value, VmError> {
+        let address = self.next_value()?;
+        self.load(address)
+    }
+
+    fn load(&self, addr: Value) -> Result<Value, VmError> {
+        let index = self.address(addr)?;
+        Ok(self.fields[index])
+    }
+
+    fn store(&mut self, addr: Value, value: Value) -> Result<(), VmError> {
+        let index = self.address(addr)?;
+        self.fields[index] = value;
+        self.writes.push((index, value));
+        Ok(())
+    }
+
+    // Checks that addr is inside of the memory
+    fn address(&self, addr: Value) -> Result<usize, VmError> {
+        if addr >= 0 && (addr as usize) < self.fields.len() {
+            Ok(addr as usize)
+        } else {
+            Err(VmError::AddressOutOfBounds { pc: self.instruction, addr })
+        }
+    }
+
+    fn jump(&mut self, target: Value) -> Result<(), VmError> {
+        // Targets past the end of memory are reported when they are executed
+        if target < 0 {
+            return Err(VmError::AddressOutOfBounds { pc: self.instruction, addr: target });
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    // Stores the result of an overflowing operation in the accumulator
+    fn arithmetic(&mut self, (result, overflow): (Value, bool)) {
+        self.accumulator = result;
+        self.overflow |= overflow;
+    }
+
+    fn divide(&mut self, divisor: Value) -> Result<(), VmError> {
+        if divisor == 0 {
+            return Err(VmError::DivisionByZero { pc: self.instruction });
+        }
+        self.arithmetic(self.accumulator.overflowing_div(divisor));
+        Ok(())
+    }
+
+    fn remainder(&mut self, divisor: Value) -> Result<(), VmError> {
+        if divisor == 0 {
+            return Err(VmError::DivisionByZero { pc: self.instruction });
+        }
+        self.arithmetic(self.accumulator.overflowing_rem(divisor));
+        Ok(())
+    }
+
+    fn input_error(&self, message: String) -> VmError {
+        VmError::InputError { pc: self.instruction, message }
+    }
+
+    fn output_error(&self, error: std::io::Error) -> VmError {
+        VmError::OutputError { pc: self.instruction, message: error.to_string() }
     }
 }
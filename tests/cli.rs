@@ -0,0 +1,36 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// Runs the binary on a source file with the given content
+fn run(name: &str, source: &str, args: &[&str]) -> Output {
+    let path: PathBuf = std::env::temp_dir().join(format!("registermaschine-cli-{}-{}.rgm", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_registermaschine")).args(args).arg(&path).output().unwrap();
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+#[test]
+fn exit_codes_tell_how_the_program_ended() {
+    let halted = run("halted", "LOADI 7\nPRINT\nHALT", &[]);
+    assert_eq!(halted.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&halted.stdout).starts_with("7\n"));
+
+    let compile_error = run("compile-error", "JUMP nowhere", &[]);
+    assert_eq!(compile_error.status.code(), Some(1));
+    assert!(compile_error.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&compile_error.stderr).contains("Unknown label 'nowhere'"));
+
+    let runtime_error = run("runtime-error", "LOADI 1\nDIVIDEI 0", &[]);
+    assert_eq!(runtime_error.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&runtime_error.stderr).contains("Division by zero"));
+
+    let exhausted = run("exhausted", "loop: JUMP loop", &["--max-steps", "10"]);
+    assert_eq!(exhausted.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&exhausted.stderr).contains("after 10 steps"));
+
+    let usage = run("usage", "HALT", &["--unknown"]);
+    assert_eq!(usage.status.code(), Some(2));
+    assert!(usage.stdout.is_empty());
+}
@@ -0,0 +1,48 @@
+use registermaschine::{assemble, disassemble, Opcode, Value};
+
+#[test]
+fn disassembly_assembles_to_the_same_memory() {
+    let source = "JUMP start\ntext: \"Hallo\"\ncount: 3\nstart: LOAD count\nloop: SUBTRACTI 1\nJUMPIFNZERO loop\nSTORE count\nHALT\nend: -1 0 2";
+    let program = assemble(source).unwrap();
+
+    let text = disassemble(&program.vm.fields, program.vm.pc, Some(&program.symbols));
+    let again = assemble(&text).unwrap_or_else(|errors| panic!("{}\n{:?}", text, errors));
+    assert_eq!(again.vm.fields, program.vm.fields);
+    assert_eq!(again.symbols, program.symbols);
+}
+
+#[test]
+fn code_is_found_by_following_jumps() {
+    let fields = [Opcode::JUMP as Value, 3, 65, Opcode::LOADI as Value, 7, Opcode::HALT as Value];
+    let text = disassemble(&fields, 0, None);
+
+    let lines: Vec<&str> = text.lines().map(|line| line.split(';').next().unwrap().trim()).collect();
+    assert_eq!(lines, ["JUMP 3", ".data 65", "LOADI 7", "HALT"]);
+}
+
+#[test]
+fn disassembly_with_numbered_and_local_labels_assembles_again() {
+    let source = ".loop: JUMP start\nstart: LOADI 3\n.loop: SUBTRACTI 1\n1$: JUMPIFZERO 1f\nJUMP .loop\n1$: HALT\nvalue: 0";
+    let program = assemble(source).unwrap();
+
+    // Only the labels that can be written in source code are symbols
+    let names: Vec<&str> = program.symbols.iter().map(|(name, _)| name).collect();
+    assert_eq!(names, ["start", "start.loop", "value"]);
+
+    let text = disassemble(&program.vm.fields, program.vm.pc, Some(&program.symbols));
+    let again = assemble(&text).unwrap_or_else(|errors| panic!("{}\n{:?}", text, errors));
+    assert_eq!(again.vm.fields, program.vm.fields);
+    assert_eq!(again.symbols, program.symbols);
+}
+
+#[test]
+fn operands_use_the_last_label_at_an_address() {
+    let source = "LOAD pointer\nHALT\ndata: \"abc\"\nend_data:\npointer: data";
+    let program = assemble(source).unwrap();
+    assert_eq!(program.symbols.names_at(6), ["end_data", "pointer"]);
+
+    let text = disassemble(&program.vm.fields, program.vm.pc, Some(&program.symbols));
+    assert!(text.starts_with("    LOAD pointer "), "{}", text);
+    let again = assemble(&text).unwrap();
+    assert_eq!(again.symbols, program.symbols);
+}
@@ -0,0 +1,48 @@
+use registermaschine::{assemble, compile};
+
+fn messages(source: &str) -> Vec<String> {
+    assemble(source).unwrap_err().into_iter().map(|error| error.message).collect()
+}
+
+#[test]
+fn operators_bind_by_precedence() {
+    let vm = compile(".word 2 + 3 * 4, (2 + 3) * 4, 1 << 2 + 1, 6 & 3 | 8, 10 - 4 - 3, 100 / 10 / 5, -(2 - 5)").unwrap();
+
+    assert_eq!(vm.fields, [14, 20, 8, 10, 3, 2, 3]);
+}
+
+#[test]
+fn constants_may_use_labels_and_later_constants() {
+    let vm = compile(".equ SIZE END - start\n.equ END DOUBLE + 2\n.equ DOUBLE 2 * 2\nstart: .word SIZE, END").unwrap();
+
+    assert_eq!(vm.fields, [6, 6]);
+}
+
+#[test]
+fn overflow_and_division_by_zero_are_errors() {
+    assert_eq!(messages(".data 32767 + 1"), ["The expression does not fit into a field (from -32768 to 32767)"]);
+    assert_eq!(messages(".data -32768 * -1"), ["The expression does not fit into a field (from -32768 to 32767)"]);
+    assert_eq!(messages(".data 1 / (2 - 2)"), ["Division by zero in expression"]);
+    assert_eq!(messages(".data 1 << 16"), ["Can't shift by 16, only by 0 to 15"]);
+}
+
+#[test]
+fn constants_that_depend_on_themselves_or_are_unknown_are_errors() {
+    assert_eq!(messages(".equ A B + 1\n.equ B A * 2\n.data A"), ["The value of the constant 'A' depends on itself"]);
+    assert_eq!(messages(".equ A MISSING + 1\n.data A"), ["Unknown label 'MISSING'"]);
+}
+
+#[test]
+fn expressions_nested_too_deep_are_reported_once() {
+    let message = "The expression is nested too deep, it can have at most 64 levels";
+
+    let parentheses = format!(".data {}1{}", "(".repeat(200_000), ")".repeat(200_000));
+    assert_eq!(messages(&parentheses), [message]);
+
+    let chain = format!(".data 1{}\nHALT", " + 1".repeat(200_000));
+    assert_eq!(messages(&chain), [message]);
+
+    // Deep, but not too deep
+    let vm = compile(&format!(".data {}1{}", "(".repeat(60), ")".repeat(60))).unwrap();
+    assert_eq!(vm.fields, [1]);
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::process::Command;
+
+use registermaschine::{assemble, format_source, CompileOptions};
+
+fn format(source: &str) -> String {
+    let formatted = format_source(source, &CompileOptions::default()).unwrap();
+    assert_eq!(format_source(&formatted, &CompileOptions::default()).unwrap(), formatted, "formatting again changes the source");
+    formatted
+}
+
+#[test]
+fn formatting_aligns_lines_and_keeps_the_program() {
+    let source = "\n\nstart:   LOADI 0xff ; load\n  STORE   result   ;store it\n\n\n  LOADI  -( 2+3 )\nresult: 0d007\n";
+    let formatted = format_source(source, &CompileOptions::default()).unwrap();
+
+    assert_eq!(formatted, "start:  LOADI 0xFF    ; load\n        STORE result  ;store it\n\n        LOADI -(2 + 3)\nresult: 7\n");
+    assert_eq!(format_source(&formatted, &CompileOptions::default()).unwrap(), formatted);
+    assert_eq!(assemble(source).unwrap().vm.fields, assemble(&formatted).unwrap().vm.fields);
+}
+
+#[test]
+fn directives_and_macro_bodies_are_aligned_like_instructions() {
+    let source = ".equ SIZE   4\n.macro TWICE x\nADDI   x\n    ADDI x ; again\n.endm\nloop:   TWICE   1\n  .word 1,2 ,  SIZE\nbuffer: .space SIZE\ntext: .data \"a\\tb\"   ; string\n   HALT\n";
+    assert_eq!(format(source), "        .equ   SIZE 4
+        .macro TWICE x
+        ADDI   x
+        ADDI   x       ; again
+        .endm
+loop:   TWICE  1
+        .word  1, 2, SIZE
+buffer: .space SIZE
+text:   .data  \"a\\tb\"  ; string
+        HALT
+");
+    assert_eq!(assemble(source).unwrap().vm.fields, assemble(&format(source)).unwrap().vm.fields);
+}
+
+#[test]
+fn labels_on_their_own_line_stay_there() {
+    assert_eq!(format("start:\n  loop:\nJUMP loop\n"), "start:\nloop:\n        JUMP loop\n");
+    // A long label moves the instructions of all lines further right
+    assert_eq!(format("a_long_label: NOOP\nHALT"), "a_long_label: NOOP\n              HALT\n");
+}
+
+#[test]
+fn comments_keep_their_lines_and_are_aligned() {
+    let source = "; A header comment\n   ; indented comment\nNOOP ;short\nLOADI 1     ;  longer\n\n\n\n; after blank lines\n\n";
+    assert_eq!(format(source), "\
+; A header comment
+        ; indented comment
+        NOOP     ;short
+        LOADI 1  ;  longer
+
+; after blank lines
+");
+}
+
+#[test]
+fn negated_numbers_are_written_with_their_sign() {
+    assert_eq!(format("LOADI - 0\nLOADI -0\nLOADI - 5\nLOADI - 0x1f\nLOADI 3 - 0\nLOADI -(- 0)\n"), "        LOADI 0
+        LOADI 0
+        LOADI -5
+        LOADI -0x1F
+        LOADI 3 - 0
+        LOADI -(0)
+");
+    assert_eq!(format("LOADI - 'a'\nLOADI - -5\n"), "        LOADI -'a'\n        LOADI --5\n");
+}
+
+#[test]
+fn check_lists_the_files_that_are_not_formatted() {
+    let directory = std::env::temp_dir().join(format!("registermaschine-format-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let formatted = directory.join("formatted.rgm");
+    let unformatted = directory.join("unformatted.rgm");
+    fs::write(&formatted, "        HALT\n").unwrap();
+    fs::write(&unformatted, "HALT").unwrap();
+    let fmt = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_registermaschine")).arg("fmt").args(args).arg(&formatted).arg(&unformatted).output().unwrap();
+
+    let check = fmt(&["--check"]);
+    assert_eq!(check.status.code(), Some(1));
+    assert_eq!(String::from_utf8(check.stdout).unwrap(), format!("{} is not formatted\n", unformatted.display()));
+    assert_eq!(fs::read_to_string(&unformatted).unwrap(), "HALT");
+
+    let rewrite = fmt(&[]);
+    assert_eq!(rewrite.status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&unformatted).unwrap(), "        HALT\n");
+
+    let check = fmt(&["--check"]);
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(check.status.code(), Some(0));
+    assert!(check.stdout.is_empty());
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use registermaschine::{assemble_with, compile, BufferIo, CompileOptions, ExitReason, Image, ImageError, VM};
+
+const PROGRAM: &str = "JUMP start\nvalue: 7\nstart: LOAD value\n.loop: PRINT\nSUBTRACTI 1\nJUMPIFNZERO .loop\nHALT";
+
+fn image() -> Image {
+    Image::from_program(&assemble_with(PROGRAM, Some(Path::new("count.rgm")), &CompileOptions::default()).unwrap())
+}
+
+fn bytes(image: &Image) -> Vec<u8> {
+    let mut data = Vec::new();
+    image.write(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn images_keep_the_program_with_its_symbols_and_source_map() {
+    let image = image();
+    let read = Image::read(&mut bytes(&image).as_slice()).unwrap();
+    assert_eq!(read, image);
+
+    let program = read.to_program();
+    assert_eq!(program.symbols.get("start.loop"), Some(5));
+    assert_eq!(program.describe(7), "start.loop+2 (count.rgm:5)");
+}
+
+#[test]
+fn vms_are_saved_with_their_pc() {
+    let mut vm = compile(PROGRAM).unwrap();
+    vm.run_for(&mut BufferIo::new(""), 1).unwrap(); // Images don't keep the accumulator, so stop before LOAD
+
+    let mut data = Vec::new();
+    vm.save_image(&mut data).unwrap();
+    let mut loaded = VM::load_image(&mut data.as_slice()).unwrap();
+    assert_eq!((loaded.pc, &loaded.fields), (vm.pc, &vm.fields));
+
+    let mut io = BufferIo::new("");
+    assert_eq!(loaded.run(&mut io), Ok(ExitReason::Halted));
+    assert_eq!(io.output(), "7\n6\n5\n4\n3\n2\n1\n");
+}
+
+#[test]
+fn truncated_images_are_malformed() {
+    let data = bytes(&image());
+    for length in 0..data.len() {
+        match Image::read(&mut &data[..length]) {
+            Err(ImageError::Malformed(message)) => assert_eq!(message, "unexpected end of data"),
+            other => panic!("reading {} of {} bytes gave {:?}", length, data.len(), other),
+        }
+    }
+}
+
+#[test]
+fn other_data_is_rejected() {
+    assert!(matches!(Image::read(&mut &b"#!/bin/sh\n"[..]), Err(ImageError::BadMagic)));
+    assert!(matches!(Image::read(&mut &b"RGMO\x01\x00\x10\x00"[..]), Err(ImageError::BadMagic)));
+
+    let mut data = bytes(&image());
+    data[4] = 2;
+    assert!(matches!(Image::read(&mut data.as_slice()), Err(ImageError::UnsupportedVersion(2))));
+    data[4] = 1;
+    data[6] = 32;
+    assert!(matches!(Image::read(&mut data.as_slice()), Err(ImageError::UnsupportedWordSize(32))));
+}
+
+#[test]
+fn unknown_sections_are_skipped() {
+    let image = Image { symbols: None, source_map: None, ..image() };
+    let mut data = bytes(&image);
+    data.pop(); // The end section
+    data.extend([99, 3, 0, 0, 0, 1, 2, 3, 0]);
+
+    assert_eq!(Image::read(&mut data.as_slice()).unwrap(), image);
+}
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use registermaschine::{assemble_with, CompileOptions, Opcode, Value};
+
+// A new directory for the files of a test
+fn directory(test: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("registermaschine-include-{}-{}", std::process::id(), test));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn write(directory: &Path, name: &str, text: &str) -> PathBuf {
+    let path = directory.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, text).unwrap();
+    path
+}
+
+// Assembles the file and returns its fields or the messages of its errors
+fn assemble(path: &Path, include_paths: &[PathBuf]) -> Result<Vec<Value>, Vec<String>> {
+    let options = CompileOptions { include_paths: include_paths.to_vec(), ..CompileOptions::default() };
+    let source = fs::read_to_string(path).unwrap();
+    match assemble_with(&source, Some(path), &options) {
+        Ok(program) => Ok(program.vm.fields),
+        Err(errors) => Err(errors.into_iter().map(|error| format!("{}:{}: {}", error.file.unwrap_or_default(), error.line, error.message)).collect()),
+    }
+}
+
+#[test]
+fn includes_are_resolved_relative_to_the_including_file() {
+    let dir = directory("relative");
+    let main = write(&dir, "main.rgm", "LOADI 1\n.include \"lib/print.rgm\"\nHALT");
+    write(&dir, "lib/print.rgm", ".include \"value.rgm\"\nPRINT");
+    write(&dir, "lib/value.rgm", "ADDI 2");
+
+    let fields = assemble(&main, &[]).unwrap();
+    assert_eq!(fields, [Opcode::LOADI as Value, 1, Opcode::ADDI as Value, 2, Opcode::PRINT as Value, Opcode::HALT as Value]);
+}
+
+#[test]
+fn errors_name_the_file_they_are_in() {
+    let dir = directory("errors");
+    let main = write(&dir, "main.rgm", ".include \"lib.rgm\"\n.include \"missing.rgm\"\nHALT");
+    let lib = write(&dir, "lib.rgm", "NOOP\nJUMP nowhere");
+
+    assert_eq!(assemble(&main, &[]).unwrap_err(), [
+        format!("{}:2: Could not find the included file 'missing.rgm'", main.display()),
+        format!("{}:2: Unknown label 'nowhere'", lib.display()),
+    ]);
+}
+
+#[test]
+fn include_cycles_are_reported() {
+    let dir = directory("cycles");
+    let a = write(&dir, "a.rgm", ".include \"b.rgm\"\nHALT");
+    let b = write(&dir, "b.rgm", "NOOP\n.include \"a.rgm\"");
+    let own = write(&dir, "own.rgm", ".include \"own.rgm\"");
+
+    assert_eq!(assemble(&a, &[]).unwrap_err(), [
+        format!("{}:2: Include cycle: {} -> {} -> {}", b.display(), a.display(), b.display(), a.display()),
+    ]);
+    assert_eq!(assemble(&own, &[]).unwrap_err(), [
+        format!("{}:1: Include cycle: {} -> {}", own.display(), own.display(), own.display()),
+    ]);
+}
+
+#[test]
+fn the_same_file_can_be_included_twice() {
+    let dir = directory("twice");
+    let main = write(&dir, "main.rgm", ".include \"step.rgm\"\n.include \"step.rgm\"");
+    write(&dir, "step.rgm", "NOOP");
+
+    assert_eq!(assemble(&main, &[]).unwrap(), [Opcode::NOOP as Value, Opcode::NOOP as Value]);
+}
+
+#[test]
+fn include_paths_are_searched_after_the_including_directory_in_order() {
+    let dir = directory("search");
+    let main = write(&dir, "main.rgm", ".include \"a.rgm\"\n.include \"b.rgm\"\n.include \"c.rgm\"");
+    write(&dir, "a.rgm", "1");
+    write(&dir, "first/a.rgm", "11");
+    write(&dir, "first/b.rgm", "12");
+    write(&dir, "second/b.rgm", "22");
+    write(&dir, "second/c.rgm", "23");
+
+    let paths = [dir.join("first"), dir.join("second")];
+    assert_eq!(assemble(&main, &paths).unwrap(), [1, 12, 23]);
+    assert_eq!(assemble(&main, &[dir.join("second"), dir.join("first")]).unwrap(), [1, 22, 23]);
+}
@@ -0,0 +1,80 @@
+use std::io;
+
+use registermaschine::{compile, BufferIo, ExitReason, FnIo, MachineIo, VmError};
+
+// Reads a line and prints it in upper case, then prints its length
+const UPPER_CASE: &str = "
+loop:   GETC
+        STORE char
+        SUBTRACTI 10
+        JUMPIFZERO done
+        LOAD count
+        ADDI 1
+        STORE count
+        LOAD char
+        SUBTRACTI 32
+        PRINTC
+        JUMP loop
+done:   LOADI 10
+        PRINTC
+        LOAD count
+        PRINT
+        HALT
+char:   0
+count:  0
+";
+
+// Implements only the required methods, so PRINT goes through write_char
+struct Characters(Vec<char>);
+
+impl MachineIo for Characters {
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        Ok(None)
+    }
+
+    fn write_char(&mut self, c: char) -> io::Result<()> {
+        self.0.push(c);
+        Ok(())
+    }
+}
+
+#[test]
+fn buffer_io_collects_the_output_and_keeps_unread_input() {
+    let mut vm = compile(UPPER_CASE).unwrap();
+    let mut io = BufferIo::new("abc\nrest");
+
+    assert_eq!(vm.run(&mut io), Ok(ExitReason::Halted));
+    assert_eq!(io.output(), "ABC\n3\n");
+    assert_eq!(io.remaining_input(), "rest");
+    assert_eq!(io.into_output(), "ABC\n3\n");
+}
+
+#[test]
+fn fn_io_forwards_to_closures() {
+    let mut vm = compile(UPPER_CASE).unwrap();
+    let mut input = "xyz\n".chars();
+    let mut output = String::new();
+
+    let result = vm.run(&mut FnIo::new(|| input.next(), |c| output.push(c)));
+    assert_eq!(result, Ok(ExitReason::Halted));
+    assert_eq!(output, "XYZ\n3\n");
+}
+
+#[test]
+fn numbers_are_written_as_characters_by_default() {
+    let mut vm = compile("LOADI -12\nPRINT\nHALT").unwrap();
+    let mut io = Characters(Vec::new());
+
+    assert_eq!(vm.run(&mut io), Ok(ExitReason::Halted));
+    assert_eq!(io.0, ['-', '1', '2', '\n']);
+}
+
+#[test]
+fn running_out_of_input_is_an_error() {
+    let mut vm = compile(UPPER_CASE).unwrap();
+    let mut output = String::new();
+
+    let result = vm.run(&mut FnIo::new(|| None, |c| output.push(c)));
+    assert_eq!(result, Err(VmError::InputError { pc: 0, message: "End of input".to_string() }));
+    assert!(output.is_empty());
+}
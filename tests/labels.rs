@@ -0,0 +1,50 @@
+use registermaschine::{assemble, compile, Opcode, Value};
+
+fn messages(source: &str) -> Vec<(usize, usize, String)> {
+    assemble(source).unwrap_err().into_iter().map(|error| (error.line, error.column, error.message)).collect()
+}
+
+const JUMP: Value = Opcode::JUMP as Value;
+
+#[test]
+fn local_labels_belong_to_the_global_label_before_them() {
+    let vm = compile("first: NOOP\n.loop: JUMP .loop\nsecond: NOOP\n.loop: JUMP .loop").unwrap();
+    assert_eq!(vm.fields[1..3], [JUMP, 1]);
+    assert_eq!(vm.fields[4..6], [JUMP, 4]);
+}
+
+#[test]
+fn numbered_labels_refer_to_the_closest_definition() {
+    // A definition on the line of the use is before it
+    let vm = compile("1$: NOOP\nJUMP 1f\nJUMP 1b\n1$: JUMP 1b").unwrap();
+    assert_eq!(vm.fields, [Opcode::NOOP as Value, JUMP, 5, JUMP, 0, JUMP, 5]);
+}
+
+#[test]
+fn missing_numbered_labels_are_reported() {
+    assert_eq!(messages("JUMP 1b\n1$: HALT"), [(1, 6, "There is no label 1$ before this use of 1b".to_string())]);
+    assert_eq!(messages("1$: JUMP 1f\nHALT"), [(1, 10, "There is no label 1$ after this use of 1f".to_string())]);
+    assert_eq!(messages("2$: JUMP 1b"), [(1, 10, "There is no label 1$ before this use of 1b".to_string())]);
+}
+
+#[test]
+fn labels_can_only_be_defined_once() {
+    let errors = assemble("start: NOOP\n.loop: NOOP\n.loop: HALT\nother: NOOP\n.loop: HALT\nstart: HALT").unwrap_err();
+    let found: Vec<(usize, &str)> = errors.iter().map(|error| (error.line, error.message.as_str())).collect();
+    assert_eq!(found, [
+        (3, "The label 'start.loop' is defined more than once"),
+        (6, "The label 'start' is defined more than once"),
+    ]);
+    assert_eq!(errors[0].notes[0].line, 2);
+    assert_eq!(errors[0].notes[0].message, "first defined here");
+}
+
+#[test]
+fn local_labels_can_be_data_on_their_own_line() {
+    let vm = compile("start: LOADI 1\n.loop: JUMP .loop\n.tab:\n  .loop\n  .tab").unwrap();
+    assert_eq!(vm.fields[2..], [JUMP, 2, 2, 4]);
+
+    // Names that aren't local labels of the scope are taken for directives
+    assert_eq!(messages("start: HALT\n.tab: 1\nother: .tab"), [(3, 8, "Unknown label 'other.tab'".to_string())]);
+    assert_eq!(messages("start: HALT\n.wrod 1"), [(2, 1, "Unknown directive '.wrod'".to_string())]);
+}
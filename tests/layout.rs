@@ -0,0 +1,92 @@
+use registermaschine::{assemble, compile, Opcode, Value};
+
+fn values(text: &str) -> Vec<Value> {
+    text.chars().map(|c| c as Value).collect()
+}
+
+#[test]
+fn label_after_non_ascii_string() {
+    let assembly = assemble("text: \"Größe\"\nafter: 7").unwrap();
+
+    assert_eq!(assembly.symbols.get("after"), Some(5));
+    assert_eq!(assembly.vm.fields[..5], values("Größe")[..]);
+    assert_eq!(assembly.vm.fields[5], 7);
+}
+
+#[test]
+fn operand_refers_to_label_after_string() {
+    let vm = compile("JUMP start\n.data \"Größe\"\nstart: HALT").unwrap();
+
+    // JUMP and its operand, then five characters
+    assert_eq!(vm.fields[1], 7);
+    assert_eq!(vm.fields[7], Opcode::HALT as Value);
+}
+
+#[test]
+fn number_label_after_string() {
+    let vm = compile("\"abc\"\n10: 42").unwrap();
+
+    assert_eq!(vm.fields.len(), 11);
+    assert_eq!(vm.fields[..3], values("abc")[..]);
+    assert!(vm.fields[3..10].iter().all(|field| *field == 0));
+    assert_eq!(vm.fields[10], 42);
+}
+
+#[test]
+fn number_label_after_non_ascii_string() {
+    let vm = compile("\"Grüße\" 8: 1").unwrap();
+
+    assert_eq!(vm.fields.len(), 9);
+    assert_eq!(vm.fields[5..8], [0, 0, 0]);
+    assert_eq!(vm.fields[8], 1);
+}
+
+#[test]
+fn terminated_strings_with_umlauts() {
+    let assembly = assemble("a: .zstring \"Öl\"\nb: .pstring \"Größe\"\nc: 0").unwrap();
+
+    assert_eq!(assembly.symbols.get("b"), Some(3));
+    assert_eq!(assembly.symbols.get("c"), Some(9));
+    assert_eq!(assembly.vm.fields[..3], [values("Öl"), vec![0]].concat()[..]);
+    assert_eq!(assembly.vm.fields[3], 5);
+}
+
+#[test]
+fn source_ending_in_non_ascii_text_is_scanned_completely() {
+    // The last token used to be cut off when the source contained multi-byte characters
+    let vm = compile("\"ä\" 1 2").unwrap();
+    assert_eq!(vm.fields, [values("ä"), vec![1, 2]].concat());
+
+    let vm = compile("5 ; Größe\n6").unwrap();
+    assert_eq!(vm.fields, [5, 6]);
+}
+
+#[test]
+fn reserved_fields_move_the_following_labels() {
+    let assembly = assemble(".equ N 3\nw: .word 1, 2\ns: .space N\nf: .fill 2, 7\n.align 4\na: .array buf[N]\nend: 0").unwrap();
+
+    assert_eq!(assembly.symbols.get("s"), Some(2));
+    assert_eq!(assembly.symbols.get("f"), Some(5));
+    assert_eq!(assembly.symbols.get("buf"), Some(8));
+    assert_eq!(assembly.symbols.get("end"), Some(11));
+    assert_eq!(assembly.vm.fields, [1, 2, 0, 0, 0, 7, 7, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn org_places_fields_anywhere_and_fills_gaps() {
+    let assembly = assemble("data: 5\n.org 4\nstart: HALT\n.org 1\n6\n.entry start").unwrap();
+
+    assert_eq!(assembly.symbols.get("start"), Some(4));
+    assert_eq!(assembly.vm.fields, [5, 6, 0, 0, Opcode::HALT as Value]);
+    assert_eq!(assembly.vm.pc, 4);
+
+    let errors = compile("1 2 3\n.org 2\n4 5").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "These fields overlap with other fields at address 2");
+    assert_eq!((errors[0].line, errors[0].column), (2, 1));
+    assert_eq!(errors[0].notes[0].message, "the other fields are placed here");
+    assert_eq!((errors[0].notes[0].line, errors[0].notes[0].column), (1, 1));
+
+    let errors = compile("1 2 3 4\n.org 1\n5 6").unwrap_err();
+    assert_eq!(errors[0].message, "These fields overlap with other fields at addresses 1 to 2");
+}
@@ -0,0 +1,84 @@
+use registermaschine::{assemble, assemble_object, link, CompileOptions, LinkError, Object};
+
+fn object(source: &str, name: &str) -> Object {
+    let mut object = assemble_object(source, None, &CompileOptions::default()).unwrap();
+    object.name = name.to_string();
+    object
+}
+
+#[test]
+fn modules_are_placed_after_each_other_and_imports_resolved() {
+    let main = object(".import double\nstart: LOAD value\nJUMP double\nvalue: 21\ndone: HALT\n.export done\n.entry start", "main");
+    let library = object(".import done\n.export double\ndouble: MULTIPLYI 2\nJUMP done\nunused: 0", "library");
+    let other = object(".export other\nother: 1", "other");
+
+    let program = link(&[main], &[other, library.clone()]).unwrap();
+
+    // The library starts after the 6 fields of main, and other isn't linked
+    assert_eq!(program.vm.fields.len(), 6 + 5);
+    assert_eq!(program.vm.fields[1], 4);        // LOAD value
+    assert_eq!(program.vm.fields[3], 6);        // JUMP double
+    assert_eq!(program.vm.fields[9], 5);        // JUMP done
+    assert_eq!(program.symbols.get("unused"), Some(10));
+    assert_eq!(program.vm.pc, 0);
+
+    // Objects keep their relocations when they are stored
+    let mut data = Vec::new();
+    library.write(&mut data).unwrap();
+    let loaded = Object::read(&mut data.as_slice()).unwrap();
+    assert_eq!(loaded.relocations, library.relocations);
+    assert_eq!(loaded.exports, ["double"]);
+}
+
+#[test]
+fn missing_and_duplicate_exports_are_reported() {
+    let main = object(".import missing\nLOAD missing\nHALT", "main");
+    let first = object(".export x\nx: 1", "first");
+    let second = object(".export x\nx: 2", "second");
+
+    let errors = link(&[main, first, second], &[]).unwrap_err();
+    assert_eq!(errors, [
+        LinkError::Unresolved { name: "missing".to_string(), module: "main".to_string() },
+        LinkError::DuplicateExport { name: "x".to_string(), first: "first".to_string(), second: "second".to_string() },
+    ]);
+}
+
+#[test]
+fn modules_can_not_be_aligned() {
+    let errors = assemble_object("NOOP\n.align 4\nvalue: 1", None, &CompileOptions::default()).unwrap_err();
+    let errors: Vec<(usize, &str)> = errors.iter().map(|error| (error.line, error.message.as_str())).collect();
+    assert_eq!(errors, [(2, ".align can't be used in modules, since the address of the module is only known when it is linked")]);
+
+    // Programs that aren't linked start at 0, so they can be aligned
+    let program = assemble("NOOP\n.align 4\nvalue: 1").unwrap();
+    assert_eq!(program.symbols.get("value"), Some(4));
+}
+
+#[test]
+fn the_entry_point_of_a_module_can_not_be_imported() {
+    let errors = assemble_object(".import start\nNOOP\n.entry start", None, &CompileOptions::default()).unwrap_err();
+    let errors: Vec<(usize, &str)> = errors.iter().map(|error| (error.line, error.message.as_str())).collect();
+    assert_eq!(errors, [(3, "The entry point has to be in the module, but 'start' is imported")]);
+}
+
+#[test]
+fn only_one_module_can_set_the_entry_point() {
+    let main = object("start: HALT\n.entry start", "main");
+    let other = object("NOOP\nbegin: HALT\n.entry begin", "other");
+
+    let errors = link(&[main.clone(), other.clone()], &[]).unwrap_err();
+    assert_eq!(errors, [LinkError::MultipleEntries { first: "main".to_string(), second: "other".to_string() }]);
+
+    // The entry point is moved with its module
+    let program = link(&[object("NOOP\nNOOP", "first"), other], &[]).unwrap();
+    assert_eq!(program.vm.pc, 3);
+}
+
+#[test]
+fn relocated_fields_have_to_fit_into_a_field() {
+    let first = object("1 2 3 4 5 6 7 8 9 10", "first");
+    let second = object("NOOP\nlast: last + 32760", "second");
+
+    let errors = link(&[first, second], &[]).unwrap_err();
+    assert_eq!(errors, [LinkError::AddressOutOfRange { module: "second".to_string(), address: 1 }]);
+}
@@ -0,0 +1,31 @@
+use registermaschine::{assemble, lint, CompileOptions, Severity};
+
+fn warnings(source: &str) -> Vec<(usize, String)> {
+    let diagnostics = lint(source, None, &CompileOptions::default()).unwrap();
+    assert!(diagnostics.iter().all(|diagnostic| diagnostic.severity == Severity::Warning));
+    diagnostics.into_iter().map(|diagnostic| (diagnostic.line, diagnostic.message)).collect()
+}
+
+#[test]
+fn likely_mistakes_are_warned_about() {
+    let source = "start:  LOADI count\n        ADDI 1\n        STORE code\n        JUMPIFZERO done\n        JUMP start\n        PRINT\ndone:   PRINT\ncount:  5\nunused: 0\ncode:   HALT";
+
+    assert_eq!(warnings(source), [
+        (1, "LOADI loads the address of 'count'. Use LOAD to load its value".to_string()),
+        (3, "STORE writes into the code of the program, which modifies itself".to_string()),
+        (6, "This code is never executed".to_string()),
+        (8, "This data is executed as code".to_string()),
+        (9, "The label 'unused' is never used".to_string()),
+        (10, "This code is never executed".to_string()),
+    ]);
+    // Pointers and programs that end with HALT are fine
+    assert!(warnings("LOADI text\nSTORE pointer\nLOAD pointer\nLOADIND\nPRINTC\nHALT\ntext: \"a\"\npointer: 0").is_empty());
+}
+
+#[test]
+fn misspelled_instruction_suggests_the_closest_one() {
+    let errors = assemble("LOADI 3\nloop: SUBSTRACTI 1\nJUMPIFNZERO loop\nhalt").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+
+    assert_eq!(messages, ["Unknown instruction 'SUBSTRACTI', did you mean SUBTRACTI?", "Unknown instruction 'halt', did you mean HALT?"]);
+}
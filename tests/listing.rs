@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use registermaschine::{assemble_with, CompileOptions, Opcode, Value};
+
+fn listing(source: &str, file: Option<&Path>) -> String {
+    let options = CompileOptions { listing: true, ..CompileOptions::default() };
+    assemble_with(source, file, &options).unwrap().listing.unwrap()
+}
+
+#[test]
+fn lines_show_the_address_and_values_of_their_fields() {
+    let source = "zeta:   LOADI 1\n        ; Only a comment\n        .word 1, 2, 3, 4, 5, 6\nalpha:  HALT";
+    let expected = format!(
+"1      0  {:>6}      1                zeta:   LOADI 1
+2                                              ; Only a comment
+3      2       1      2      3      4          .word 1, 2, 3, 4, 5, 6
+       6       5      6
+4      8  {:>6}                       alpha:  HALT
+
+Symbols:
+    8  alpha
+    0  zeta
+", Opcode::LOADI as Value, Opcode::HALT as Value);
+
+    assert_eq!(listing(source, None), expected);
+}
+
+#[test]
+fn macros_are_listed_at_their_use() {
+    let source = ".macro TWICE x\nADDI x\nADDI x\n.endm\nTWICE 3";
+    let add = Opcode::ADDI as Value;
+    let text = listing(source, None);
+    assert!(text.contains(&format!("5      0  {:>6}      3 {:>6}      3  TWICE 3\n", add, add)), "{}", text);
+}
+
+#[test]
+fn included_files_are_listed_after_a_header() {
+    let directory = std::env::temp_dir().join(format!("registermaschine-listing-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let main = directory.join("main.rgm");
+    fs::write(&main, "start: LOADI 1\n.include \"inc.rgm\"\nvalue:  HALT\n").unwrap();
+    fs::write(directory.join("inc.rgm"), "MOVEI 7 value\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_registermaschine")).arg("--listing").arg(&main).output().unwrap();
+    let text = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(lines[0], format!("; {}", main.display()));
+    assert_eq!(lines[1], format!("1      0  {:>6}      1                start: LOADI 1", Opcode::LOADI as Value));
+    assert_eq!(lines[4], format!("; {}", directory.join("inc.rgm").display()));
+    assert_eq!(lines[5], format!("1      2  {:>6}      7      5         MOVEI 7 value", Opcode::MOVEI as Value));
+    assert_eq!(lines[6..], ["", "Symbols:", "    0  start", "    5  value"]);
+}
@@ -0,0 +1,44 @@
+use registermaschine::{assemble, assemble_with, compile, CompileOptions, Opcode, Value};
+
+#[test]
+fn macro_uses_are_replaced_with_the_body_and_arguments() {
+    let vm = compile(".macro ADD2 a b\nLOADI a\nADDI b\n.endm\nADD2 3 4\nADD2 (1 + 1) x\nHALT\nx: 5").unwrap();
+
+    let code = [Opcode::LOADI as Value, 3, Opcode::ADDI as Value, 4, Opcode::LOADI as Value, 2, Opcode::ADDI as Value, 9];
+    assert_eq!(vm.fields[..8], code);
+}
+
+#[test]
+fn labels_in_macros_are_unique_for_every_use() {
+    let source = ".macro COUNTDOWN n\nLOADI n\nagain: SUBTRACTI 1\nJUMPIFNZERO again\n.endm\nagain: COUNTDOWN 2\nCOUNTDOWN 3\nJUMP again\nHALT";
+    let options = CompileOptions { listing: true, ..CompileOptions::default() };
+    let program = assemble_with(source, None, &options).unwrap();
+
+    // Every use jumps back into its own body, the label outside of the macro is a different one
+    assert_eq!(program.vm.fields[5], 2);
+    assert_eq!(program.vm.fields[11], 8);
+    assert_eq!(program.vm.fields[13], 0);
+
+    // The names given to labels in macros can't be written in source, so they aren't symbols
+    let names: Vec<&str> = program.symbols.iter().map(|(name, _)| name).collect();
+    assert_eq!(names, ["again"]);
+    assert!(!program.listing.unwrap().contains('#'));
+}
+
+#[test]
+fn macros_that_use_themselves_are_nested_too_deep() {
+    let errors = assemble(".macro LOOP\nNOOP\nLOOP\n.endm\nLOOP\nHALT").unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "Macros are nested too deep");
+    assert_eq!(errors[0].line, 5);
+    assert_eq!(errors[0].notes[0].message, "maybe 'LOOP' uses itself here");
+}
+
+#[test]
+fn macros_expect_their_arguments_on_the_same_line() {
+    let errors = assemble(".macro ADD2 a b\nLOADI a\nADDI b\n.endm\nADD2 1\n2\nHALT").unwrap_err();
+
+    assert_eq!(errors[0].message, "The macro 'ADD2' expects 2 arguments, found 1");
+    assert_eq!(errors[0].line, 5);
+}
@@ -0,0 +1,69 @@
+use std::convert::TryFrom;
+use std::process::Command;
+
+use registermaschine::{opcode_help, Opcode, OperandKind, Value, OPCODES};
+
+#[test]
+fn opcodes_are_stored_at_their_code() {
+    for (index, info) in OPCODES.iter().enumerate() {
+        assert_eq!(info.code, index as Value, "{}", info.mnemonic);
+        assert_eq!(info.opcode as Value, info.code, "{}", info.mnemonic);
+        assert_eq!(info.opcode.info(), info);
+        assert_eq!(info.opcode.to_string(), info.mnemonic);
+    }
+}
+
+#[test]
+fn every_code_decodes_to_its_opcode() {
+    for info in OPCODES {
+        assert_eq!(Opcode::decode(info.code), Some(info.opcode));
+        assert_eq!(Opcode::try_from(info.code), Ok(info.opcode));
+    }
+    let unused = OPCODES.len() as Value;
+    assert_eq!(Opcode::decode(unused), None);
+    assert_eq!(Opcode::decode(-1), None);
+    assert_eq!(Opcode::try_from(unused), Err(unused));
+    assert_eq!(Opcode::try_from(Value::MIN), Err(Value::MIN));
+}
+
+#[test]
+fn mnemonics_are_found_with_and_without_case() {
+    for info in OPCODES {
+        assert_eq!(Opcode::from_mnemonic(info.mnemonic), Some(info.opcode));
+        assert_eq!(Opcode::from_mnemonic_ignore_case(&info.mnemonic.to_lowercase()), Some(info.opcode));
+    }
+    assert_eq!(Opcode::from_mnemonic("halt"), None);
+    assert_eq!(Opcode::from_mnemonic("Halt"), None);
+    assert_eq!(Opcode::from_mnemonic_ignore_case("Halt"), Some(Opcode::HALT));
+    assert_eq!(Opcode::from_mnemonic_ignore_case("HALTS"), None);
+    assert_eq!(Opcode::from_mnemonic(""), None);
+}
+
+#[test]
+fn operands_and_cycles_come_from_the_table() {
+    assert_eq!(Opcode::HALT.operands(), &[]);
+    assert_eq!(Opcode::LOADI.operands(), &[OperandKind::Immediate]);
+    assert_eq!(Opcode::MOVEI.operands(), &[OperandKind::Immediate, OperandKind::Address]);
+    assert_eq!(Opcode::MOVE.operands(), &[OperandKind::Address, OperandKind::Address]);
+    for info in OPCODES {
+        assert_eq!(info.opcode.cycles(), info.cycles);
+        assert!(info.cycles > 0, "{}", info.mnemonic);
+    }
+}
+
+#[test]
+fn the_help_lists_every_opcode_in_order() {
+    let help = opcode_help();
+    let lines: Vec<&str> = help.lines().collect();
+    assert_eq!(lines.len(), OPCODES.len());
+    for (line, info) in lines.iter().zip(OPCODES) {
+        assert!(line.starts_with(&format!("{:3} {} ", info.code, info.mnemonic)), "{}", line);
+        assert!(line.ends_with(info.description), "{}", line);
+    }
+    assert!(help.contains(&format!("{:3} MOVEI value address ", Opcode::MOVEI as Value)));
+    assert!(help.contains(&format!("{:3} LOAD address ", Opcode::LOAD as Value)));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_registermaschine")).arg("--opcodes").output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), help);
+}
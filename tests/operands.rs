@@ -0,0 +1,47 @@
+use registermaschine::{assemble, compile, Opcode, Value};
+
+fn messages(source: &str) -> Vec<(usize, usize, String)> {
+    assemble(source).unwrap_err().into_iter().map(|error| (error.line, error.column, error.message)).collect()
+}
+
+#[test]
+fn missing_operands_are_reported() {
+    assert_eq!(messages("ADD\nHALT"), [(1, 1, "ADD expects 1 operand, found 0".to_string())]);
+    assert_eq!(messages("MOVE 1\nHALT"), [(1, 1, "MOVE expects 2 operands, found 1".to_string())]);
+    assert_eq!(messages("JUMP"), [(1, 1, "JUMP expects 1 operand, found 0".to_string())]);
+}
+
+#[test]
+fn operands_have_to_be_on_the_same_line() {
+    assert_eq!(messages("LOADI\n5\nHALT"), [
+        (1, 1, "LOADI expects 1 operand, found 0. Operands have to be on the same line as the instruction".to_string()),
+    ]);
+    assert_eq!(messages("MOVEI 1\n2"), [
+        (1, 1, "MOVEI expects 2 operands, found 1. Operands have to be on the same line as the instruction".to_string()),
+    ]);
+}
+
+#[test]
+fn superfluous_operands_are_reported() {
+    assert_eq!(messages("NOT 5\nHALT"), [
+        (1, 5, "Too many operands: NOT expects no operands. Use .data for raw data".to_string()),
+    ]);
+    // Only the first superfluous value is reported, also on the following lines
+    assert_eq!(messages("LOADI 1 2\n3\nHALT"), [
+        (1, 9, "Too many operands: LOADI expects 1 operand. Use .data for raw data".to_string()),
+    ]);
+}
+
+#[test]
+fn operands_must_fit_their_kind() {
+    assert_eq!(messages("LOADI \"a\""), [(1, 7, "A string can't be an operand of LOADI".to_string())]);
+    assert_eq!(messages("STORE -1"), [(1, 7, "STORE expects an address, which can't be negative".to_string())]);
+    // The unterminated string is only reported once
+    assert_eq!(messages("LOADI \"abc\nHALT"), [(1, 7, "Unterminated string, expected \" before the end of the line".to_string())]);
+}
+
+#[test]
+fn data_after_instructions_is_written_with_data() {
+    let vm = compile("HALT\n.data 1 2\nx: 3").unwrap();
+    assert_eq!(vm.fields, [Opcode::HALT as Value, 1, 2, 3]);
+}
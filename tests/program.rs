@@ -0,0 +1,16 @@
+use registermaschine::{assemble, Image};
+
+#[test]
+fn addresses_are_described_by_label_and_source_line() {
+    let program = assemble("start: LOADI 6\nloop: SUBTRACTI 1\n  JUMPIFNZERO loop\n  HALT").unwrap();
+
+    assert_eq!(program.describe(0), "start (line 1)");
+    assert_eq!(program.describe(5), "loop+3 (line 3)");
+    assert_eq!(program.source_map.get(5).map(|location| location.column), Some(15));
+
+    // Images keep the symbols and source map
+    let mut data = Vec::new();
+    Image::from_program(&program).write(&mut data).unwrap();
+    let loaded = Image::read(&mut data.as_slice()).unwrap().to_program();
+    assert_eq!(loaded.describe(5), "loop+3 (line 3)");
+}
@@ -0,0 +1,149 @@
+use registermaschine::{assemble, assemble_with, compile, CompileOptions, Diagnostic, Opcode, Span, Value};
+
+fn messages(source: &str) -> Vec<(usize, usize, String)> {
+    assemble(source).unwrap_err().into_iter().map(|error| (error.line, error.column, error.message)).collect()
+}
+
+#[test]
+fn long_runs_of_comments_and_whitespace_are_skipped() {
+    let source = format!("{}{}HALT", "; comment\n".repeat(300_000), " \t\n".repeat(300_000));
+    let vm = compile(&source).unwrap();
+
+    assert_eq!(vm.fields, [Opcode::HALT as Value]);
+}
+
+#[test]
+fn disallowed_characters_are_reported_once_per_run() {
+    let errors = assemble(&format!("{}\nHALT", "@".repeat(300_000))).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].message.starts_with("Unexpected characters '@@@"));
+
+    assert_eq!(messages("LOADI 1 @ ADDI 2\n~#"), [
+        (1, 9, "Unexpected character '@'".to_string()),
+        (2, 1, "Unexpected characters '~#'".to_string()),
+    ]);
+}
+
+#[test]
+fn malformed_strings_and_characters_are_reported() {
+    assert_eq!(messages("\"open\nHALT"), [(1, 1, "Unterminated string, expected \" before the end of the line".to_string())]);
+    assert_eq!(messages("\"a\\qb\""), [(1, 3, "Unknown escape sequence '\\q'".to_string())]);
+    assert_eq!(messages("LOADI ''"), [(1, 7, "Empty character literal".to_string())]);
+    assert_eq!(messages("LOADI 'ab'"), [(1, 7, "Expected ' after the character".to_string())]);
+}
+
+#[test]
+fn diagnostics_show_the_line_with_the_span_underlined() {
+    let error = assemble("LOADI 1\n\tJUMP strat").unwrap_err().remove(0);
+    assert_eq!(error.to_string(), "error: Unknown label 'strat'\n --> 2:7\n  |\n2 | \tJUMP strat\n  | \t     ^^^^^");
+
+    let source = "x: 1\nx: 2";
+    let diagnostic = Diagnostic::error(source, Span::new(5, 7), "defined twice")
+        .with_note(Diagnostic::note(source, Span::new(0, 2), "first defined here"))
+        .in_file("a.rgm");
+    assert_eq!(diagnostic.to_string(), "error: defined twice\n --> a.rgm:2:1\n  |\n2 | x: 2\n  | ^^\nnote: first defined here\n --> 1:1\n  |\n1 | x: 1\n  | ^^");
+}
+
+#[test]
+fn numbers_can_be_written_in_several_bases() {
+    let vm = compile("0x1F\n0xffff\n0b1001_0011\n0o17\n0d99\n-0x2\n0x-2\n1_000\n-32768").unwrap();
+    assert_eq!(vm.fields, [31, -1, 0b1001_0011, 15, 99, -2, -2, 1000, -32768]);
+}
+
+#[test]
+fn characters_are_stored_as_their_code() {
+    let vm = compile("'a'\n'\\n'\n'\\''\n'\\\\'\n'\\0'\n'\\u{E4}'\n'ä'").unwrap();
+    assert_eq!(vm.fields, ['a' as Value, 10, '\'' as Value, '\\' as Value, 0, 0xE4, 0xE4]);
+}
+
+#[test]
+fn malformed_numbers_are_reported() {
+    assert_eq!(messages("32768"), [(1, 1, "Number 32768 does not fit into a field (from -32768 to 32767)".to_string())]);
+    assert_eq!(messages("-32769"), [(1, 1, "Number -32769 does not fit into a field (from -32768 to 32767)".to_string())]);
+    assert_eq!(messages("0x10000"), [(1, 1, "Number 65536 does not fit into a field (from -32768 to 65535)".to_string())]);
+    assert_eq!(messages("0b102"), [(1, 1, "Invalid digit in base 2 number".to_string())]);
+    assert_eq!(messages("12ab"), [(1, 1, "Invalid digit in base 10 number".to_string())]);
+    assert_eq!(messages("0x"), [(1, 1, "Expected digits after the prefix for base 16".to_string())]);
+    assert_eq!(messages("-0x-1"), [(1, 1, "A number can only have one sign".to_string())]);
+    assert_eq!(messages("'\\u{1F600}'"), [(1, 1, "Character '\u{1F600}' does not fit into a field".to_string())]);
+}
+
+#[test]
+fn numbered_labels_start_at_1() {
+    // 0b is always a binary number, so 0$ could only be used forwards
+    assert_eq!(messages("0$: JUMP 0f\n0$: HALT"), [
+        (1, 1, "Numbered labels start at 1, since 0b is a binary number and can't refer to 0$".to_string()),
+        (2, 1, "Numbered labels start at 1, since 0b is a binary number and can't refer to 0$".to_string()),
+    ]);
+    assert_eq!(messages("1$: JUMP 0b"), [
+        (1, 10, "Expected digits after the prefix for base 2. 0b is no label, numbered labels start at 1".to_string()),
+    ]);
+
+    let vm = compile("1$: JUMP 1f\n1$: JUMP 1b").unwrap();
+    assert_eq!(vm.fields, [Opcode::JUMP as Value, 2, Opcode::JUMP as Value, 2]);
+}
+
+#[test]
+fn names_have_letters_digits_underscores_and_dots() {
+    let program = assemble("print_num: NOOP\nloop2: NOOP\nx1: NOOP\n_tmp: NOOP\nlist.end: NOOP\nJUMP print_num\nJUMP loop2").unwrap();
+    let names: Vec<(&str, usize)> = program.symbols.iter().collect();
+    assert_eq!(names, [("_tmp", 3), ("list.end", 4), ("loop2", 1), ("print_num", 0), ("x1", 2)]);
+    assert_eq!(program.vm.fields[5..], [Opcode::JUMP as Value, 0, Opcode::JUMP as Value, 1]);
+
+    // A digit starts a number, not a name
+    assert_eq!(messages("2x: HALT"), [(1, 1, "A label can't start with a digit".to_string())]);
+    assert_eq!(messages("JUMP 2x"), [(1, 6, "Invalid digit in base 10 number".to_string())]);
+}
+
+#[test]
+fn names_can_not_be_instructions() {
+    assert_eq!(messages("HALT: 1"), [(1, 1, "The label can't be named like the instruction HALT".to_string())]);
+    assert_eq!(messages(".equ ADD 3\nHALT"), [(1, 6, "The constant can't be named like the instruction ADD".to_string())]);
+    assert_eq!(messages(".macro PRINT\n.endm\nHALT"), [(1, 8, "The macro can't be named like the instruction PRINT".to_string())]);
+
+    // Instructions are only matched exactly, unless case is ignored
+    let program = assemble("halt: JUMP halt").unwrap();
+    assert_eq!(program.symbols.get("halt"), Some(0));
+}
+
+#[test]
+fn instructions_can_be_written_in_any_case_if_case_is_ignored() {
+    let options = CompileOptions { ignore_case: true, ..CompileOptions::default() };
+    let program = assemble_with("loadi 1\nPrint\nHALT", None, &options).unwrap();
+    assert_eq!(program.vm.fields, [Opcode::LOADI as Value, 1, Opcode::PRINT as Value, Opcode::HALT as Value]);
+
+    let errors = assemble_with("halt: JUMP halt", None, &options).unwrap_err();
+    assert_eq!(errors[0].message, "The label can't be named like the instruction HALT");
+    assert_eq!(messages("halt"), [(1, 1, "Unknown instruction 'halt', did you mean HALT?".to_string())]);
+}
+
+#[test]
+fn strings_take_one_field_per_character() {
+    let vm = compile(r#""a\n\t\r\0\\\"\'\u{E4}ä""#).unwrap();
+    let expected: Vec<Value> = "a\n\t\r\0\\\"'ää".chars().map(|c| c as Value).collect();
+    assert_eq!(vm.fields, expected);
+}
+
+#[test]
+fn strings_can_be_terminated_or_prefixed_with_their_length() {
+    let vm = compile(".zstring \"hi\"\n.pstring \"hi\"\n.pstring \"\"\n.zstring \"\"").unwrap();
+    let (h, i) = ('h' as Value, 'i' as Value);
+    assert_eq!(vm.fields, [h, i, 0, 2, h, i, 0, 0]);
+
+    assert_eq!(messages(".zstring 5"), [(1, 1, "Expected a string after .zstring".to_string())]);
+    assert_eq!(messages(".pstring\n\"hi\""), [(1, 1, "Expected a string after .pstring".to_string())]);
+}
+
+#[test]
+fn malformed_escape_sequences_are_reported() {
+    assert_eq!(messages(r#""\u{}""#), [(1, 2, "Expected a character code like \\u{E4}".to_string())]);
+    assert_eq!(messages(r#""\u{1234567}""#), [(1, 2, "Expected a character code like \\u{E4}".to_string())]);
+    assert_eq!(messages(r#""\uE4""#), [(1, 2, "Expected a character code like \\u{E4}".to_string())]);
+    assert_eq!(messages(r#""\u{D800}""#), [(1, 2, "D800 is not a valid character code".to_string())]);
+    assert_eq!(messages(r#""\u{10000}""#), [(1, 2, "Character '\u{10000}' does not fit into a field".to_string())]);
+    // An unterminated string ends with its line, so the next line is still assembled
+    assert_eq!(messages("\"abc\\\"\nJUMP nowhere"), [
+        (1, 1, "Unterminated string, expected \" before the end of the line".to_string()),
+        (2, 6, "Unknown label 'nowhere'".to_string()),
+    ]);
+}
@@ -0,0 +1,205 @@
+use std::io;
+use std::time::Duration;
+
+use registermaschine::{compile, BufferIo, ExitReason, Limits, MachineIo, Opcode, Step, Value, VmError, VM};
+
+// A program of opcodes and operands, loaded at field 0
+fn load(fields: &[Value]) -> VM {
+    let mut vm = VM::new();
+    vm.fields = fields.to_vec();
+    vm
+}
+
+fn op(opcode: Opcode) -> Value {
+    opcode as Value
+}
+
+// Output that always fails
+struct BrokenOutput;
+
+impl MachineIo for BrokenOutput {
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        Err(io::Error::other("input closed"))
+    }
+
+    fn write_char(&mut self, _: char) -> io::Result<()> {
+        Err(io::Error::other("output closed"))
+    }
+}
+
+#[test]
+fn subtract_and_divide_use_the_field_once() {
+    let mut vm = compile("LOADI 10\nSUBTRACT three\nSTORE result\nLOADI 12\nDIVIDE three\nADD result\nHALT\nthree: 3\nresult: 0").unwrap();
+
+    assert_eq!(vm.run(&mut BufferIo::new("")), Ok(ExitReason::Halted));
+    assert_eq!(vm.accumulator, 4 + 7);
+}
+
+#[test]
+fn overflow_wraps_and_sets_the_flag_until_it_is_tested() {
+    let mut vm = compile("LOADI 32767\nADDI 1\nSTORE wrapped\nJUMPIFOVERFLOW overflowed\nHALT\noverflowed: JUMPIFOVERFLOW again\nLOADI 1\nHALT\nagain: HALT\nwrapped: 0").unwrap();
+
+    assert_eq!(vm.run(&mut BufferIo::new("")), Ok(ExitReason::Halted));
+    assert_eq!(vm.fields[vm.fields.len() - 1], Value::MIN);
+    assert_eq!(vm.accumulator, 1); // The second JUMPIFOVERFLOW doesn't jump
+    assert!(!vm.overflow);
+}
+
+#[test]
+fn errors_leave_pc_at_the_failing_instruction() {
+    // LOADI 5, then the error at 2, which must not change the accumulator or the memory
+    let cases = [
+        (vec![op(Opcode::LOADI), 5, 99], VmError::InvalidOpcode { pc: 2, value: 99 }),
+        (vec![op(Opcode::LOADI), 5, op(Opcode::STORE), 100], VmError::AddressOutOfBounds { pc: 2, addr: 100 }),
+        (vec![op(Opcode::LOADI), 5, op(Opcode::LOAD), -1], VmError::AddressOutOfBounds { pc: 2, addr: -1 }),
+        (vec![op(Opcode::LOADI), 5, op(Opcode::JUMP), -3], VmError::AddressOutOfBounds { pc: 2, addr: -3 }),
+        (vec![op(Opcode::LOADI), 5, op(Opcode::DIVIDEI), 0], VmError::DivisionByZero { pc: 2 }),
+        (vec![op(Opcode::LOADI), 5, op(Opcode::REMAINDER), 4, 0], VmError::DivisionByZero { pc: 2 }),
+        (vec![op(Opcode::LOADI), 5, op(Opcode::MOVEI), 1], VmError::PcOutOfRange { pc: 2 }),
+        (vec![op(Opcode::LOADI), 5, op(Opcode::GETC)], VmError::InputError { pc: 2, message: "End of input".to_string() }),
+    ];
+    for (fields, error) in cases {
+        let mut vm = load(&fields);
+        assert_eq!(vm.run(&mut BufferIo::new("")), Err(error.clone()));
+        assert_eq!(vm.pc, 2, "{}", error);
+        assert_eq!(vm.accumulator, 5, "{}", error);
+        assert_eq!(vm.steps, 1, "{}", error);
+        assert_eq!(vm.fields, fields, "{}", error);
+
+        // Stepping again fails the same way
+        assert_eq!(vm.step(&mut BufferIo::new("")), Err(error));
+    }
+}
+
+#[test]
+fn running_past_the_end_of_memory_is_an_error() {
+    let mut vm = load(&[op(Opcode::LOADI), 5]);
+
+    assert_eq!(vm.run(&mut BufferIo::new("")), Err(VmError::PcOutOfRange { pc: 2 }));
+    assert_eq!(vm.pc, 2);
+}
+
+#[test]
+fn io_errors_are_reported() {
+    let mut vm = load(&[op(Opcode::LOADI), 65, op(Opcode::PRINTC)]);
+    assert_eq!(vm.run(&mut BrokenOutput), Err(VmError::OutputError { pc: 2, message: "output closed".to_string() }));
+    assert_eq!(vm.pc, 2);
+
+    let mut vm = load(&[op(Opcode::GETC)]);
+    assert_eq!(vm.run(&mut BrokenOutput), Err(VmError::InputError { pc: 0, message: "input closed".to_string() }));
+    assert_eq!(vm.pc, 0);
+}
+
+#[test]
+fn the_step_limit_stops_before_the_next_instruction() {
+    let mut vm = compile("loop: ADDI 1\nJUMP loop").unwrap();
+    vm.limits = Limits { max_steps: Some(5), time_limit: None };
+
+    assert_eq!(vm.run(&mut BufferIo::new("")), Ok(ExitReason::BudgetExhausted { pc: 2, steps: 5 }));
+    assert_eq!(vm.accumulator, 3);
+
+    // The steps are counted over the lifetime of the vm, so running again stops at once
+    assert_eq!(vm.run(&mut BufferIo::new("")), Ok(ExitReason::BudgetExhausted { pc: 2, steps: 5 }));
+    vm.limits.max_steps = Some(6);
+    assert_eq!(vm.run(&mut BufferIo::new("")), Ok(ExitReason::BudgetExhausted { pc: 0, steps: 6 }));
+}
+
+#[test]
+fn the_time_limit_stops_endless_loops() {
+    let mut vm = compile("loop: JUMP loop").unwrap();
+    vm.limits = Limits { max_steps: None, time_limit: Some(Duration::from_millis(20)) };
+
+    match vm.run(&mut BufferIo::new("")) {
+        Ok(ExitReason::BudgetExhausted { pc, steps }) => {
+            assert_eq!(pc, 0);
+            assert!(steps > 0);
+        }
+        other => panic!("expected the time limit to stop the vm, got {:?}", other),
+    }
+}
+
+#[test]
+fn programs_within_the_limits_halt() {
+    let mut vm = compile("LOADI 1\nHALT").unwrap();
+    vm.limits = Limits { max_steps: Some(2), time_limit: Some(Duration::from_secs(10)) };
+
+    assert_eq!(vm.run(&mut BufferIo::new("")), Ok(ExitReason::Halted));
+}
+
+// Counts down from 3, storing every value in count at 9
+const COUNTDOWN: &str = "LOADI 3\nloop: SUBTRACTI 1\nSTORE count\nJUMPIFNZERO loop\nHALT\ncount: 0";
+
+#[test]
+fn step_reports_what_the_instruction_did() {
+    let mut vm = compile(COUNTDOWN).unwrap();
+    let mut io = BufferIo::new("");
+
+    let step = vm.step(&mut io).unwrap();
+    assert_eq!(step, Step { pc: 0, opcode: Opcode::LOADI, operands: vec![3], writes: vec![], halted: false });
+    let step = vm.step(&mut io).unwrap();
+    assert_eq!(step, Step { pc: 2, opcode: Opcode::SUBTRACTI, operands: vec![1], writes: vec![], halted: false });
+    let step = vm.step(&mut io).unwrap();
+    assert_eq!(step, Step { pc: 4, opcode: Opcode::STORE, operands: vec![9], writes: vec![(9, 2)], halted: false });
+    assert_eq!((vm.pc, vm.steps, vm.cycles), (6, 3, 4));
+}
+
+#[test]
+fn stepping_a_halted_vm_halts_again() {
+    let mut vm = compile(COUNTDOWN).unwrap();
+    let mut io = BufferIo::new("");
+    vm.pc = 8;
+
+    for steps in 1..=2 {
+        let step = vm.step(&mut io).unwrap();
+        assert_eq!(step, Step { pc: 8, opcode: Opcode::HALT, operands: vec![], writes: vec![], halted: true });
+        assert_eq!((vm.pc, vm.steps), (8, steps));
+    }
+}
+
+#[test]
+fn run_for_pauses_after_the_given_number_of_steps() {
+    let mut vm = compile(COUNTDOWN).unwrap();
+    let mut io = BufferIo::new("");
+
+    assert_eq!(vm.run_for(&mut io, 0), Ok(ExitReason::Paused));
+    assert_eq!((vm.pc, vm.steps), (0, 0));
+    assert_eq!(vm.run_for(&mut io, 2), Ok(ExitReason::Paused));
+    assert_eq!((vm.pc, vm.steps, vm.accumulator), (4, 2, 2));
+
+    // The whole program takes 11 steps, the last one halts instead of pausing
+    assert_eq!(vm.run_for(&mut io, 9), Ok(ExitReason::Halted));
+    assert_eq!((vm.pc, vm.steps, vm.fields[9]), (8, 11, 0));
+}
+
+#[test]
+fn run_until_stops_at_breakpoints() {
+    let mut vm = compile(COUNTDOWN).unwrap();
+    let mut io = BufferIo::new("");
+
+    // A breakpoint at JUMPIFNZERO, which pauses before it is executed
+    for count in (0..3).rev() {
+        assert_eq!(vm.run_until(&mut io, |vm, _| vm.pc == 6), Ok(ExitReason::Paused));
+        assert_eq!((vm.pc, vm.fields[9]), (6, count));
+    }
+    assert_eq!(vm.run_until(&mut io, |vm, _| vm.pc == 6), Ok(ExitReason::Halted));
+    assert_eq!(vm.pc, 8);
+}
+
+#[test]
+fn run_until_stops_at_watched_fields() {
+    let mut vm = compile(COUNTDOWN).unwrap();
+    let mut io = BufferIo::new("");
+
+    let watch = |_: &VM, step: &Step| step.writes.iter().any(|(address, value)| *address == 9 && *value == 1);
+    assert_eq!(vm.run_until(&mut io, watch), Ok(ExitReason::Paused));
+    assert_eq!((vm.pc, vm.steps, vm.fields[9]), (6, 6, 1));
+}
+
+#[test]
+fn halting_takes_precedence_over_pausing() {
+    let mut vm = compile(COUNTDOWN).unwrap();
+
+    assert_eq!(vm.run_until(&mut BufferIo::new(""), |vm, _| vm.pc == 8), Ok(ExitReason::Paused));
+    assert_eq!(vm.run_until(&mut BufferIo::new(""), |_, _| true), Ok(ExitReason::Halted));
+    assert_eq!(vm.run_for(&mut BufferIo::new(""), 1), Ok(ExitReason::Halted));
+}